@@ -1,5 +1,6 @@
 //! (TODO) Hardware AES calculator (AES)
 use crate::pac::AES;
+use crate::sysctl::{self, APB1};
 use core::marker::PhantomData;
 
 pub struct Aes<MODE, KLEN> {
@@ -22,39 +23,39 @@ pub struct K256;
 
 #[allow(unused)] // todo: remove
 impl<MODE, KLEN> Aes<MODE, KLEN> {
-    pub fn ecb128(aes: AES) -> Aes<Ecb, K128> {
+    pub fn ecb128(aes: AES, apb1: &mut APB1, gate: sysctl::AES) -> Aes<Ecb, K128> {
         todo!()
     }
 
-    pub fn ecb192(aes: AES) -> Aes<Ecb, K192> {
+    pub fn ecb192(aes: AES, apb1: &mut APB1, gate: sysctl::AES) -> Aes<Ecb, K192> {
         todo!()
     }
 
-    pub fn ecb256(aes: AES) -> Aes<Ecb, K256> {
+    pub fn ecb256(aes: AES, apb1: &mut APB1, gate: sysctl::AES) -> Aes<Ecb, K256> {
         todo!()
     }
 
-    pub fn cbc128(aes: AES) -> Aes<Cbc, K128> {
+    pub fn cbc128(aes: AES, apb1: &mut APB1, gate: sysctl::AES) -> Aes<Cbc, K128> {
         todo!()
     }
 
-    pub fn cbc192(aes: AES) -> Aes<Cbc, K192> {
+    pub fn cbc192(aes: AES, apb1: &mut APB1, gate: sysctl::AES) -> Aes<Cbc, K192> {
         todo!()
     }
 
-    pub fn cbc256(aes: AES) -> Aes<Cbc, K256> {
+    pub fn cbc256(aes: AES, apb1: &mut APB1, gate: sysctl::AES) -> Aes<Cbc, K256> {
         todo!()
     }
 
-    pub fn gcm128(aes: AES) -> Aes<Gcm, K128> {
+    pub fn gcm128(aes: AES, apb1: &mut APB1, gate: sysctl::AES) -> Aes<Gcm, K128> {
         todo!()
     }
 
-    pub fn gcm192(aes: AES) -> Aes<Gcm, K192> {
+    pub fn gcm192(aes: AES, apb1: &mut APB1, gate: sysctl::AES) -> Aes<Gcm, K192> {
         todo!()
     }
 
-    pub fn gcm256(aes: AES) -> Aes<Gcm, K256> {
+    pub fn gcm256(aes: AES, apb1: &mut APB1, gate: sysctl::AES) -> Aes<Gcm, K256> {
         todo!()
     }
 }
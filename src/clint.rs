@@ -23,6 +23,167 @@ pub mod msip {
     pub fn clear_ipi(hart_id: usize) {
         unsafe { (*pac::CLINT::ptr()).msip[hart_id].write(|w| w.bits(0)) }
     }
+
+    /// Order preceding memory stores before following device stores
+    ///
+    /// Atomic orderings don't cover MMIO, so a hart woken by an IPI could
+    /// otherwise miss data published right before it was raised.
+    #[inline]
+    pub(crate) fn fence_w_o() {
+        // note(unsafe): a fence has no other effect
+        #[cfg(target_arch = "riscv64")]
+        unsafe {
+            core::arch::asm!("fence w, o", options(nostack, preserves_flags))
+        };
+        #[cfg(not(target_arch = "riscv64"))]
+        core::sync::atomic::fence(core::sync::atomic::Ordering::SeqCst);
+    }
+
+    /// Order preceding device stores before following memory loads
+    ///
+    /// Counterpart of `fence_w_o` on the receiving hart: an IPI cleared
+    /// after the loads could otherwise wipe one raised in between.
+    #[inline]
+    pub(crate) fn fence_o_r() {
+        // note(unsafe): a fence has no other effect
+        #[cfg(target_arch = "riscv64")]
+        unsafe {
+            core::arch::asm!("fence o, r", options(nostack, preserves_flags))
+        };
+        #[cfg(not(target_arch = "riscv64"))]
+        core::sync::atomic::fence(core::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+/// Inter-hart mailboxes on top of msip
+///
+/// A [`Mailbox`](mailbox::Mailbox) is a fixed-size lock-free queue towards one
+/// hart. Sending raises the software interrupt of that hart, whose
+/// `MachineSoft` handler drains the queue:
+///
+/// ```ignore
+/// static JOBS: Mailbox<Job, 8> = Mailbox::new(1);
+///
+/// // hart 0
+/// let mut tx = JOBS.sender().unwrap();
+/// tx.send(job)?;
+///
+/// // hart 1, in its MachineSoft handler
+/// for job in rx.drain() {
+///     run(job);
+/// }
+/// ```
+pub mod mailbox {
+    use super::msip;
+    use core::cell::UnsafeCell;
+    use core::mem::MaybeUninit;
+    use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    /// Single producer, single consumer queue of up to N messages for a hart
+    pub struct Mailbox<T, const N: usize> {
+        hart_id: usize,
+        slots: [UnsafeCell<MaybeUninit<T>>; N],
+        /// count of messages received, wrapping
+        head: AtomicUsize,
+        /// count of messages sent, wrapping
+        tail: AtomicUsize,
+        sender_taken: AtomicBool,
+        receiver_taken: AtomicBool,
+    }
+
+    // note(unsafe): slots are only touched through the one Sender and the one
+    // Receiver, ordered by head and tail
+    unsafe impl<T: Send, const N: usize> Sync for Mailbox<T, N> {}
+
+    impl<T, const N: usize> Mailbox<T, N> {
+        /// Creates an empty mailbox towards the given hart
+        pub const fn new(hart_id: usize) -> Self {
+            Mailbox {
+                hart_id,
+                slots: [const { UnsafeCell::new(MaybeUninit::uninit()) }; N],
+                head: AtomicUsize::new(0),
+                tail: AtomicUsize::new(0),
+                sender_taken: AtomicBool::new(false),
+                receiver_taken: AtomicBool::new(false),
+            }
+        }
+
+        /// Takes the sending end, `None` if it was taken before
+        pub fn sender(&'static self) -> Option<Sender<T, N>> {
+            match self.sender_taken.swap(true, Ordering::AcqRel) {
+                false => Some(Sender { mailbox: self }),
+                true => None,
+            }
+        }
+
+        /// Takes the receiving end, `None` if it was taken before
+        pub fn receiver(&'static self) -> Option<Receiver<T, N>> {
+            match self.receiver_taken.swap(true, Ordering::AcqRel) {
+                false => Some(Receiver { mailbox: self }),
+                true => None,
+            }
+        }
+
+        fn slot(&self, index: usize) -> *mut MaybeUninit<T> {
+            self.slots[index % N].get()
+        }
+    }
+
+    /// Sending end of a mailbox
+    pub struct Sender<T: 'static, const N: usize> {
+        mailbox: &'static Mailbox<T, N>,
+    }
+
+    impl<T, const N: usize> Sender<T, N> {
+        /// Queues a message and interrupts the receiving hart
+        ///
+        /// The message is given back if the mailbox is full.
+        pub fn send(&mut self, message: T) -> Result<(), T> {
+            let mailbox = self.mailbox;
+            let tail = mailbox.tail.load(Ordering::Relaxed);
+            let head = mailbox.head.load(Ordering::Acquire);
+            if tail.wrapping_sub(head) == N {
+                return Err(message);
+            }
+            unsafe { (*mailbox.slot(tail)).write(message) };
+            mailbox.tail.store(tail.wrapping_add(1), Ordering::Release);
+            // the receiver clears msip before reading tail, see `drain`
+            msip::fence_w_o();
+            msip::set_ipi(mailbox.hart_id);
+            Ok(())
+        }
+    }
+
+    /// Receiving end of a mailbox
+    pub struct Receiver<T: 'static, const N: usize> {
+        mailbox: &'static Mailbox<T, N>,
+    }
+
+    impl<T, const N: usize> Receiver<T, N> {
+        /// Takes the oldest message, if any
+        pub fn recv(&mut self) -> Option<T> {
+            let mailbox = self.mailbox;
+            let head = mailbox.head.load(Ordering::Relaxed);
+            let tail = mailbox.tail.load(Ordering::Acquire);
+            if head == tail {
+                return None;
+            }
+            let message = unsafe { (*mailbox.slot(head)).assume_init_read() };
+            mailbox.head.store(head.wrapping_add(1), Ordering::Release);
+            Some(message)
+        }
+
+        /// Clears the software interrupt and takes every queued message
+        ///
+        /// The interrupt is cleared first, so a message sent while draining
+        /// either shows up here or raises the interrupt again.
+        pub fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
+            msip::clear_ipi(self.mailbox.hart_id);
+            // the clear has to land before tail is read
+            msip::fence_o_r();
+            core::iter::from_fn(move || self.recv())
+        }
+    }
 }
 
 /// mtimecmp register
@@ -35,8 +196,325 @@ pub mod mtimecmp {
     }
 
     /// Write 64-bit mtimecmp register for certain hart id
+    ///
+    /// The register is set to its maximum first, so no spurious timer
+    /// interrupt fires even if the bus splits the write into two halves.
     pub fn write(hart_id: usize, bits: u64) {
         // Volume II: RISC-V Privileged Architectures V1.10 p.31, figure 3.15
-        unsafe { (*pac::CLINT::ptr()).mtimecmp[hart_id].write(|w| w.bits(bits)) };
+        unsafe {
+            let mtimecmp = &(*pac::CLINT::ptr()).mtimecmp[hart_id];
+            mtimecmp.write(|w| w.bits(u64::MAX));
+            mtimecmp.write(|w| w.bits(bits));
+        }
+    }
+}
+
+/// Software alarms sharing the mtimecmp register of a hart
+///
+/// Keep the queue where both the program and the machine timer interrupt
+/// handler can reach it, e.g. in a static behind a critical section mutex,
+/// and call `AlarmQueue::on_interrupt` from the handler. The machine timer
+/// interrupt (`mie.MTIE`) has to be enabled by the program.
+pub mod alarm {
+    use super::{mtime, mtimecmp};
+    use crate::time::Instant;
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    /// What happens when an alarm goes off
+    #[derive(Clone, Copy)]
+    pub enum Action {
+        /// Call the function from the interrupt handler
+        Callback(fn()),
+        /// Set the flag, for the program to poll
+        Flag(&'static AtomicBool),
+    }
+
+    impl Action {
+        fn fire(self) {
+            match self {
+                Action::Callback(callback) => callback(),
+                Action::Flag(flag) => flag.store(true, Ordering::Release),
+            }
+        }
+    }
+
+    /// Handle of a scheduled alarm, to cancel it
+    ///
+    /// Slots are reused once their alarm goes off, so the handle also holds
+    /// the generation of the slot; a stale handle never refers to a newer alarm.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct AlarmId {
+        index: usize,
+        generation: u32,
+    }
+
+    /// Error of scheduling an alarm on a queue with every slot taken
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct Full;
+
+    #[derive(Clone, Copy)]
+    struct Alarm {
+        deadline: u64,
+        action: Action,
+    }
+
+    #[derive(Clone, Copy)]
+    struct Slot {
+        /// Bumped whenever an alarm is scheduled into the slot
+        generation: u32,
+        alarm: Option<Alarm>,
+    }
+
+    /// Queue of up to N alarms of one hart
+    pub struct AlarmQueue<const N: usize> {
+        hart_id: usize,
+        slots: [Slot; N],
+    }
+
+    impl<const N: usize> AlarmQueue<N> {
+        /// Creates an empty queue for the given hart
+        pub const fn new(hart_id: usize) -> Self {
+            AlarmQueue {
+                hart_id,
+                slots: [Slot {
+                    generation: 0,
+                    alarm: None,
+                }; N],
+            }
+        }
+
+        /// Schedules an action at deadline
+        ///
+        /// A deadline in the past fires on the next timer interrupt, which is
+        /// right away if interrupts are enabled.
+        pub fn schedule(&mut self, deadline: Instant, action: Action) -> Result<AlarmId, Full> {
+            let index = self
+                .slots
+                .iter()
+                .position(|slot| slot.alarm.is_none())
+                .ok_or(Full)?;
+            let slot = &mut self.slots[index];
+            slot.generation = slot.generation.wrapping_add(1);
+            slot.alarm = Some(Alarm {
+                deadline: deadline.0,
+                action,
+            });
+            let id = AlarmId {
+                index,
+                generation: slot.generation,
+            };
+            self.reprogram();
+            Ok(id)
+        }
+
+        /// Cancels an alarm, returning whether it was still pending
+        pub fn cancel(&mut self, id: AlarmId) -> bool {
+            if !self.is_pending(id) {
+                return false;
+            }
+            self.slots[id.index].alarm = None;
+            self.reprogram();
+            true
+        }
+
+        /// Returns whether the alarm has not gone off yet
+        pub fn is_pending(&self, id: AlarmId) -> bool {
+            let slot = &self.slots[id.index];
+            slot.generation == id.generation && slot.alarm.is_some()
+        }
+
+        /// Fires expired alarms and programs the nearest deadline left
+        ///
+        /// Call this from the machine timer interrupt handler of the hart.
+        pub fn on_interrupt(&mut self) {
+            loop {
+                let now = mtime::read();
+                for slot in self.slots.iter_mut() {
+                    if let Some(alarm) = slot.alarm {
+                        if alarm.deadline <= now {
+                            slot.alarm = None;
+                            alarm.action.fire();
+                        }
+                    }
+                }
+                // a deadline may have passed while firing; go again without
+                // waiting for another interrupt
+                match self.reprogram() {
+                    Some(next) if next <= mtime::read() => continue,
+                    _ => break,
+                }
+            }
+        }
+
+        /// Program mtimecmp with the nearest deadline, or disarm it
+        fn reprogram(&self) -> Option<u64> {
+            let next = self
+                .slots
+                .iter()
+                .filter_map(|slot| slot.alarm)
+                .map(|alarm| alarm.deadline)
+                .min();
+            mtimecmp::write(self.hart_id, next.unwrap_or(u64::MAX));
+            next
+        }
+    }
+}
+
+/// RTIC monotonic on `mtime`, with the mtimecmp of one hart as its alarm
+#[cfg(feature = "rtic-monotonic")]
+pub mod rtic {
+    use super::{mtime, mtimecmp};
+    use crate::clock::Clocks;
+    use fugit::{TimerDurationU64, TimerInstantU64};
+    use riscv::register::mie;
+    use rtic_monotonic::Monotonic;
+
+    /// Monotonic timer ticking at `TIMER_HZ`, the CLINT timebase frequency
+    ///
+    /// `mtime` is never reset, so instants count from chip reset. The CPU
+    /// frequency must not change while the monotonic is in use.
+    pub struct MtimeMonotonic<const TIMER_HZ: u32> {
+        hart_id: usize,
+    }
+
+    impl<const TIMER_HZ: u32> MtimeMonotonic<TIMER_HZ> {
+        /// Creates the monotonic for a hart
+        ///
+        /// # Panics
+        ///
+        /// Panics if `TIMER_HZ` is not the `mtime` frequency of the clocks.
+        pub fn new(clocks: &Clocks, hart_id: usize) -> Self {
+            assert_eq!(
+                clocks.mtime().0,
+                TIMER_HZ,
+                "TIMER_HZ does not match the mtime frequency"
+            );
+            MtimeMonotonic { hart_id }
+        }
+    }
+
+    impl<const TIMER_HZ: u32> Monotonic for MtimeMonotonic<TIMER_HZ> {
+        type Instant = TimerInstantU64<TIMER_HZ>;
+        type Duration = TimerDurationU64<TIMER_HZ>;
+
+        fn now(&mut self) -> Self::Instant {
+            Self::Instant::from_ticks(mtime::read())
+        }
+
+        fn set_compare(&mut self, instant: Self::Instant) {
+            mtimecmp::write(self.hart_id, instant.ticks());
+        }
+
+        fn clear_compare_flag(&mut self) {
+            // the pending bit follows mtime >= mtimecmp
+            mtimecmp::write(self.hart_id, u64::MAX);
+        }
+
+        fn zero() -> Self::Instant {
+            Self::Instant::from_ticks(0)
+        }
+
+        unsafe fn reset(&mut self) {
+            // mtime is shared by both harts, leave it running
+        }
+
+        fn enable_timer(&mut self) {
+            unsafe { mie::set_mtimer() };
+        }
+
+        fn disable_timer(&mut self) {
+            unsafe { mie::clear_mtimer() };
+        }
+    }
+}
+
+/// embassy-time driver on `mtime`, with the mtimecmp of one hart as its alarm
+///
+/// Call [`embassy::init`] on that hart once before using `embassy-time`, and
+/// [`embassy::on_interrupt`] from the machine timer interrupt handler of that
+/// hart. The CPU frequency must not change afterwards.
+#[cfg(feature = "embassy-time")]
+pub mod embassy {
+    use super::{mtime, mtimecmp};
+    use crate::clock::Clocks;
+    use core::cell::RefCell;
+    use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+    use core::task::Waker;
+    use critical_section::Mutex;
+    use embassy_time_driver::{Driver, TICK_HZ};
+    use embassy_time_queue_utils::Queue;
+    use riscv::register::mie;
+
+    struct MtimeDriver {
+        hart_id: AtomicUsize,
+        mtime_hz: AtomicU32,
+        queue: Mutex<RefCell<Queue>>,
+    }
+
+    embassy_time_driver::time_driver_impl!(static DRIVER: MtimeDriver = MtimeDriver {
+        hart_id: AtomicUsize::new(0),
+        mtime_hz: AtomicU32::new(0),
+        queue: Mutex::new(RefCell::new(Queue::new())),
+    });
+
+    /// Sets the tick rate from the clocks and the hart whose mtimecmp is used
+    ///
+    /// Call this on that hart: it also enables the machine timer interrupt
+    /// (`mie.MTIE`) there. Machine interrupts still have to be enabled
+    /// globally for alarms to fire.
+    pub fn init(clocks: &Clocks, hart_id: usize) {
+        DRIVER.hart_id.store(hart_id, Ordering::Relaxed);
+        DRIVER.mtime_hz.store(clocks.mtime().0, Ordering::Relaxed);
+        mtimecmp::write(hart_id, u64::MAX);
+        unsafe { mie::set_mtimer() };
+    }
+
+    /// Wakes expired timers and programs the next alarm
+    pub fn on_interrupt() {
+        critical_section::with(|cs| {
+            let mut queue = DRIVER.queue.borrow(cs).borrow_mut();
+            DRIVER.rearm(&mut queue);
+        });
+    }
+
+    impl MtimeDriver {
+        /// Program the next alarm, until one is set in the future
+        fn rearm(&self, queue: &mut Queue) {
+            let mut next = queue.next_expiration(self.now());
+            while !self.set_alarm(next) {
+                next = queue.next_expiration(self.now());
+            }
+        }
+
+        /// Program mtimecmp for an embassy tick, false if it has passed already
+        fn set_alarm(&self, at: u64) -> bool {
+            let hart_id = self.hart_id.load(Ordering::Relaxed);
+            if at == u64::MAX {
+                mtimecmp::write(hart_id, u64::MAX);
+                return true;
+            }
+            let mtime_hz = self.mtime_hz.load(Ordering::Relaxed) as u128;
+            let ticks = (at as u128 * mtime_hz).div_ceil(TICK_HZ as u128);
+            mtimecmp::write(hart_id, ticks.min(u64::MAX as u128) as u64);
+            self.now() < at
+        }
+    }
+
+    impl Driver for MtimeDriver {
+        fn now(&self) -> u64 {
+            match self.mtime_hz.load(Ordering::Relaxed) as u128 {
+                0 => 0,
+                mtime_hz => (mtime::read() as u128 * TICK_HZ as u128 / mtime_hz) as u64,
+            }
+        }
+
+        fn schedule_wake(&self, at: u64, waker: &Waker) {
+            critical_section::with(|cs| {
+                let mut queue = self.queue.borrow(cs).borrow_mut();
+                if queue.schedule_wake(at, waker) {
+                    self.rearm(&mut queue);
+                }
+            });
+        }
     }
 }
@@ -1,34 +1,106 @@
 //! Clock configuration
-//use crate::pac::PRCI;
-// use crate::sysctl::ACLK;
+//!
+//! [`ClockConfig`] describes the desired frequencies of the clock tree; freezing
+//! it programs the system controller and returns the resulting [`Clocks`].
+//! The clock tree can be changed again later on, for example to run slower
+//! while idle. Drivers implementing [`Retime`] passed along are then given
+//! the new frequencies to recompute their dividers.
+//!
+//! Clock settings go through `sysctl::ClockControl`, so this keeps working
+//! after drivers took their gate tokens out of `sysctl::Parts`:
+//!
+//! ```no_run
+//! use k210_hal::clock::ClockConfig;
+//! use k210_hal::pac::Peripherals;
+//! use k210_hal::prelude::*;
+//!
+//! let p = Peripherals::take().unwrap();
+//! let mut sysctl = p.SYSCTL.constrain();
+//! let clocks = ClockConfig::new()
+//!     .cpu(400.mhz())
+//!     .apb0(200.mhz())
+//!     .freeze(&mut sysctl.clock);
+//! let mut serial = p.UART1.configure(115_200.bps(), &clocks, sysctl.uart1);
+//!
+//! let clocks = sysctl
+//!     .clock
+//!     .set_cpu_frequency(26.mhz(), &mut [&mut serial]);
+//! ```
+use crate::sysctl::{self, ClockEnables, ClockTree, Divider, Pll2Input, PllParams, Threshold};
 use crate::time::Hertz;
+use core::fmt;
 
+/// CLINT timebase divider from ACLK
+const CLINT_DIVIDER: u32 = 50;
+
+macro_rules! def_clocks {
+    ($($clk: ident: $doc: expr;)+) => {
 /// Frozen clock frequencies
 ///
-/// The existence of this value indicates that the clock configuration can no
-/// longer be changed.
-#[derive(Clone, Copy)]
+/// This is a snapshot taken when the clock tree was configured. It goes stale
+/// once the clock tree is reconfigured, e.g. with `ClockConfig::apply` or
+/// `sysctl::ClockControl::set_cpu_frequency`; drivers keep their old timing unless
+/// they are retimed with the new value.
+#[derive(Clone, Copy, Debug)]
 pub struct Clocks {
-    pub(crate) aclk: Hertz,
-    pub(crate) apb0: Hertz,
+    $( pub(crate) $clk: Hertz, )+
 }
 
 impl Clocks {
+$(
+    #[doc = concat!("Returns ", $doc, " frequency")]
+    pub fn $clk(&self) -> Hertz {
+        self.$clk
+    }
+)+
+
+    /// (name, frequency) of every clock
+    fn entries(&self) -> impl Iterator<Item = (&'static str, Hertz)> {
+        [$( (stringify!($clk), self.$clk), )+].into_iter()
+    }
+}
+    };
+}
+
+def_clocks! {
+    in0: "external oscillator IN0";
+    pll0: "PLL0";
+    pll1: "PLL1";
+    pll2: "PLL2";
+    aclk: "ACLK";
+    apb0: "APB0";
+    apb1: "APB1";
+    apb2: "APB2";
+    sram0: "SRAM0";
+    sram1: "SRAM1";
+    ai: "AI accelerator (KPU)";
+    dvp: "DVP";
+    rom: "ROM";
+    spi0: "SPI0 source";
+    spi1: "SPI1 source";
+    spi2: "SPI2 source";
+    spi3: "SPI3 source";
+    timer0: "TIMER0 source";
+    timer1: "TIMER1 source";
+    timer2: "TIMER2 source";
+    i2s0: "I2S0 source";
+    i2s1: "I2S1 source";
+    i2s2: "I2S2 source";
+    i2s0_mclk: "I2S0 master clock";
+    i2s1_mclk: "I2S1 master clock";
+    i2s2_mclk: "I2S2 master clock";
+    i2c0: "I2C0 source";
+    i2c1: "I2C1 source";
+    i2c2: "I2C2 source";
+    wdt0: "WDT0 source";
+    wdt1: "WDT1 source";
+}
+
+impl Clocks {
+    /// Reads frequencies of the clock tree as it is configured now
     #[doc(hidden)]
     pub fn new() -> Self {
-        /*
-                [MAIXPY]Pll0:freq:806000000
-                [MAIXPY]Pll1:freq:398666666
-                [MAIXPY]Pll2:freq:45066666
-                [MAIXPY]cpu:freq:403000000
-                [MAIXPY]kpu:freq:398666666
-                in freq: 26000000
-                cpu_freq: 390000000
-        */
-        Self {
-            aclk: Hertz(390_000_000),
-            apb0: Hertz(195_000_000),
-        }
+        ClockTree::read().clocks()
     }
 
     /// Returns CPU frequency
@@ -36,8 +108,355 @@ impl Clocks {
         Hertz(self.aclk.0)
     }
 
-    /// Returns APB0 frequency
-    pub fn apb0(&self) -> Hertz {
-        self.apb0
+    /// Returns CLINT timebase (`mtime`) frequency, which is CPU frequency / 50
+    pub fn mtime(&self) -> Hertz {
+        Hertz(self.aclk.0 / CLINT_DIVIDER)
+    }
+}
+
+/// Snapshot of the whole clock tree, for debugging
+///
+/// The `Display` output lists one clock per line, so it can be dumped over
+/// UARTHS with `write!(tx, "{}", ClockReport::read())`.
+#[derive(Clone, Copy, Debug)]
+pub struct ClockReport {
+    /// Frequencies of every clock
+    pub clocks: Clocks,
+    /// Whether the output of PLL0, PLL1 and PLL2 is enabled
+    pub pll_enabled: [bool; 3],
+    /// Whether ACLK (CPU) runs from PLL0 instead of IN0
+    pub aclk_from_pll0: bool,
+    /// Clock enable bits of buses and peripherals
+    pub enables: ClockEnables,
+}
+
+impl ClockReport {
+    /// Reads every clock register of sysctl
+    pub fn read() -> Self {
+        let tree = ClockTree::read();
+        ClockReport {
+            clocks: tree.clocks(),
+            pll_enabled: sysctl::pll_enabled(),
+            aclk_from_pll0: tree.aclk_pll0,
+            enables: ClockEnables::read(),
+        }
+    }
+}
+
+impl fmt::Display for ClockReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let on_off = |enabled| if enabled { "on" } else { "off" };
+        writeln!(f, "clocks:")?;
+        for (name, freq) in self.clocks.entries() {
+            writeln!(f, "  {:<10} {:>10} Hz", name, freq.0)?;
+        }
+        writeln!(f, "  {:<10} {:>10} Hz", "cpu", self.clocks.cpu().0)?;
+        writeln!(f, "sources:")?;
+        for (index, &enabled) in self.pll_enabled.iter().enumerate() {
+            writeln!(f, "  pll{:<7} {}", index, on_off(enabled))?;
+        }
+        let aclk = if self.aclk_from_pll0 { "pll0" } else { "in0" };
+        writeln!(f, "  {:<10} {}", "aclk", aclk)?;
+        writeln!(f, "enables:")?;
+        for (name, enabled) in self.enables.entries() {
+            writeln!(f, "  {:<10} {}", name, on_off(enabled))?;
+        }
+        Ok(())
+    }
+}
+
+/// Drivers whose timing is derived from the clock tree
+pub trait Retime {
+    /// Recomputes dividers for the new clock frequencies
+    fn retime(&mut self, clocks: &Clocks);
+}
+
+macro_rules! def_threshold_targets {
+    ($($clk: ident: ($Threshold: ident, $doc: expr);)+) => {
+$(
+    #[doc = concat!("Sets the target frequency of ", $doc)]
+    pub fn $clk(mut self, freq: impl Into<Hertz>) -> Self {
+        self.thresholds[Threshold::$Threshold as usize] = Some(freq.into().0);
+        self
+    }
+)+
+    };
+}
+
+/// Clock tree configuration
+///
+/// Each target is optional: clocks without a target keep their current
+/// settings. PLLs get as close to their targets as possible; buses and
+/// peripheral clocks run at the highest frequency their dividers allow without
+/// going above their targets, or at the lowest one if the target can't be
+/// reached.
+///
+/// Without an explicit PLL0 target, PLL0 is set to twice the CPU target, so
+/// that ACLK can run from it undivided. A CPU target of exactly 26 MHz makes
+/// ACLK run from IN0 directly.
+#[derive(Clone, Copy, Default)]
+pub struct ClockConfig {
+    cpu: Option<u32>,
+    pll0: Option<u32>,
+    pll1: Option<u32>,
+    pll2: Option<u32>,
+    pll2_input: Option<Pll2Input>,
+    apb: [Option<u32>; 3],
+    thresholds: [Option<u32>; Threshold::ALL.len()],
+}
+
+impl ClockConfig {
+    /// Creates a configuration that leaves every clock as it is
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the target frequency of CPU, ie. ACLK
+    pub fn cpu(mut self, freq: impl Into<Hertz>) -> Self {
+        self.cpu = Some(freq.into().0);
+        self
+    }
+
+    /// Sets the target frequency of PLL0
+    pub fn pll0(mut self, freq: impl Into<Hertz>) -> Self {
+        self.pll0 = Some(freq.into().0);
+        self
+    }
+
+    /// Sets the target frequency of PLL1
+    pub fn pll1(mut self, freq: impl Into<Hertz>) -> Self {
+        self.pll1 = Some(freq.into().0);
+        self
+    }
+
+    /// Sets the target frequency of PLL2
+    pub fn pll2(mut self, freq: impl Into<Hertz>) -> Self {
+        self.pll2 = Some(freq.into().0);
+        self
+    }
+
+    /// Selects the reference input of PLL2
+    pub fn pll2_input(mut self, input: Pll2Input) -> Self {
+        self.pll2_input = Some(input);
+        self
+    }
+
+    /// Sets the target frequency of APB0
+    pub fn apb0(mut self, freq: impl Into<Hertz>) -> Self {
+        self.apb[0] = Some(freq.into().0);
+        self
+    }
+
+    /// Sets the target frequency of APB1
+    pub fn apb1(mut self, freq: impl Into<Hertz>) -> Self {
+        self.apb[1] = Some(freq.into().0);
+        self
+    }
+
+    /// Sets the target frequency of APB2
+    pub fn apb2(mut self, freq: impl Into<Hertz>) -> Self {
+        self.apb[2] = Some(freq.into().0);
+        self
+    }
+
+    def_threshold_targets! {
+        sram0: (Sram0, "SRAM0");
+        sram1: (Sram1, "SRAM1");
+        ai: (Ai, "the AI accelerator (KPU), derived from PLL1");
+        dvp: (Dvp, "DVP");
+        rom: (Rom, "ROM");
+        spi0: (Spi0, "SPI0 source clock, derived from PLL0");
+        spi1: (Spi1, "SPI1 source clock, derived from PLL0");
+        spi2: (Spi2, "SPI2 source clock, derived from PLL0");
+        spi3: (Spi3, "SPI3 source clock, derived from IN0 or PLL0");
+        timer0: (Timer0, "TIMER0 source clock, derived from IN0 or PLL0");
+        timer1: (Timer1, "TIMER1 source clock, derived from IN0 or PLL0");
+        timer2: (Timer2, "TIMER2 source clock, derived from IN0 or PLL0");
+        i2s0: (I2s0, "I2S0 source clock, derived from PLL2");
+        i2s1: (I2s1, "I2S1 source clock, derived from PLL2");
+        i2s2: (I2s2, "I2S2 source clock, derived from PLL2");
+        i2s0_mclk: (I2s0Mclk, "I2S0 master clock, derived from PLL2");
+        i2s1_mclk: (I2s1Mclk, "I2S1 master clock, derived from PLL2");
+        i2s2_mclk: (I2s2Mclk, "I2S2 master clock, derived from PLL2");
+        i2c0: (I2c0, "I2C0 source clock, derived from PLL0");
+        i2c1: (I2c1, "I2C1 source clock, derived from PLL0");
+        i2c2: (I2c2, "I2C2 source clock, derived from PLL0");
+        wdt0: (Wdt0, "WDT0 source clock, derived from IN0");
+        wdt1: (Wdt1, "WDT1 source clock, derived from IN0");
+    }
+
+    /// Programs the clock tree and returns the frozen frequencies
+    pub fn freeze(self, clock: &mut sysctl::ClockControl) -> Clocks {
+        let current = ClockTree::read();
+        let tree = self.solve(&current);
+        clock.apply_clock_tree(&current, &tree);
+        tree.clocks()
+    }
+
+    /// Reprograms a running clock tree and retimes the given drivers
+    ///
+    /// Transfers in flight are disturbed while clocks change, so drivers
+    /// should be idle (e.g. transmitters flushed) when calling this.
+    pub fn apply(
+        self,
+        clock: &mut sysctl::ClockControl,
+        drivers: &mut [&mut dyn Retime],
+    ) -> Clocks {
+        let clocks = self.freeze(clock);
+        for driver in drivers.iter_mut() {
+            driver.retime(&clocks);
+        }
+        clocks
+    }
+
+    /// Solves the targets into register settings, starting from `current`
+    pub(crate) fn solve(&self, current: &ClockTree) -> ClockTree {
+        let mut tree = *current;
+        let in0 = sysctl::CLOCK_FREQ_IN0;
+        // PLLs
+        let cpu_pll0 = self.cpu.filter(|&cpu| cpu != in0);
+        let pll0 = self.pll0.or(cpu_pll0.map(|cpu| cpu.saturating_mul(2)));
+        if let Some(solution) = pll0.and_then(|freq| PllParams::calculate(in0, freq)) {
+            tree.pll[0] = solution.params;
+        }
+        if let Some(solution) = self.pll1.and_then(|freq| PllParams::calculate(in0, freq)) {
+            tree.pll[1] = solution.params;
+        }
+        if let Some(input) = self.pll2_input {
+            tree.pll2_input = input;
+        }
+        let pll2_input = tree.pll_input(2);
+        if let Some(solution) = self
+            .pll2
+            .and_then(|freq| PllParams::calculate(pll2_input, freq))
+        {
+            tree.pll[2] = solution.params;
+        }
+        // ACLK, aclk = pll0 / (2 << aclk_divider_sel)
+        if let Some(cpu) = self.cpu {
+            tree.aclk_pll0 = cpu != in0;
+            if tree.aclk_pll0 {
+                let pll0 = tree.pll_output(0);
+                tree.aclk_divider_sel = (0..0b11)
+                    .find(|&sel| pll0 / (2 << sel) <= cpu)
+                    .unwrap_or(0b11);
+            }
+        }
+        // buses, apb = aclk / (apb_clk_sel + 1)
+        let aclk = tree.aclk();
+        for (sel, target) in tree.apb_clk_sel.iter_mut().zip(self.apb) {
+            if let Some(freq) = target {
+                *sel = Divider::Gated.threshold(aclk, freq, 0b111) as u8;
+            }
+        }
+        // peripherals
+        for &threshold in Threshold::ALL {
+            let target = match self.thresholds[threshold as usize] {
+                Some(freq) => freq,
+                None => continue,
+            };
+            tree.fit_threshold(threshold, target);
+        }
+        tree
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysctl::CLOCK_FREQ_IN0;
+
+    /// Everything from IN0, undivided, with PLLs at 780 MHz
+    fn tree() -> ClockTree {
+        let pll = PllParams {
+            nr: 1,
+            nf: 30,
+            od: 1,
+        };
+        ClockTree {
+            pll: [pll; 3],
+            pll2_input: Pll2Input::In0,
+            aclk_pll0: false,
+            aclk_divider_sel: 0,
+            apb_clk_sel: [0; 3],
+            pll0_sel: [false; 4],
+            clk_th: [0; 7],
+        }
+    }
+
+    #[test]
+    fn cpu_target_sets_pll0_and_aclk_divider() {
+        let solved = ClockConfig::new().cpu(Hertz(390_000_000)).solve(&tree());
+        assert!(solved.aclk_pll0);
+        assert_eq!(solved.pll_output(0), 780_000_000);
+        assert_eq!(solved.aclk_divider_sel, 0);
+        assert_eq!(solved.aclk(), 390_000_000);
+
+        let solved = ClockConfig::new()
+            .pll0(Hertz(780_000_000))
+            .cpu(Hertz(200_000_000))
+            .solve(&tree());
+        assert_eq!(solved.pll_output(0), 780_000_000);
+        assert_eq!(solved.aclk_divider_sel, 1);
+        assert_eq!(solved.aclk(), 195_000_000);
+    }
+
+    #[test]
+    fn cpu_target_of_in0_selects_in0() {
+        let mut current = tree();
+        current.aclk_pll0 = true;
+        let solved = ClockConfig::new()
+            .cpu(Hertz(CLOCK_FREQ_IN0))
+            .solve(&current);
+        assert!(!solved.aclk_pll0);
+        assert_eq!(solved.pll, current.pll);
+        assert_eq!(solved.aclk(), CLOCK_FREQ_IN0);
+    }
+
+    #[test]
+    fn apb_targets_pick_largest_frequency_not_above() {
+        let solved = ClockConfig::new()
+            .cpu(Hertz(390_000_000))
+            .apb0(Hertz(100_000_000))
+            .apb1(Hertz(390_000_000))
+            .apb2(Hertz(130_000_000))
+            .solve(&tree());
+        assert_eq!(solved.apb_clk_sel, [3, 0, 2]);
+        assert_eq!(solved.apb(0), 97_500_000);
+        assert_eq!(solved.apb(1), 390_000_000);
+        assert_eq!(solved.apb(2), 130_000_000);
+    }
+
+    #[test]
+    fn fit_threshold_chooses_between_in0_and_pll0() {
+        // PLL0 gets closer from below: 780 MHz / 66 against 26 MHz / 4
+        let solved = ClockConfig::new().spi3(Hertz(12_000_000)).solve(&tree());
+        assert!(solved.pll0_sel[0]);
+        assert_eq!(solved.threshold(Threshold::Spi3), 32);
+        assert_eq!(solved.clocks().spi3.0, 11_818_181);
+        // both reach the target exactly, IN0 is kept
+        let solved = ClockConfig::new().timer0(Hertz(13_000_000)).solve(&tree());
+        assert!(!solved.pll0_sel[1]);
+        assert_eq!(solved.clocks().timer0.0, 13_000_000);
+        // PLL0 can't go low enough, IN0 can
+        let mut current = tree();
+        current.pll0_sel[2] = true;
+        let solved = ClockConfig::new().timer1(Hertz(100_000)).solve(&current);
+        assert!(!solved.pll0_sel[2]);
+        assert_eq!(solved.threshold(Threshold::Timer1), 129);
+        assert_eq!(solved.clocks().timer1.0, 100_000);
+    }
+
+    #[test]
+    fn unreachable_low_target_clamps_to_max_divider() {
+        let solved = ClockConfig::new()
+            .apb0(Hertz(1))
+            .spi0(Hertz(1))
+            .i2s0(Hertz(1))
+            .solve(&tree());
+        assert_eq!(solved.apb_clk_sel[0], 0b111);
+        assert_eq!(solved.threshold(Threshold::Spi0), Threshold::Spi0.max());
+        assert_eq!(solved.clocks().spi0.0, 780_000_000 / 512);
+        assert_eq!(solved.threshold(Threshold::I2s0), Threshold::I2s0.max());
     }
 }
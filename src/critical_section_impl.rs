@@ -0,0 +1,56 @@
+//! `critical-section` implementation for both harts
+//!
+//! A critical section disables machine interrupts on the current hart and
+//! takes a spinlock shared by both harts, so `critical_section::Mutex` also
+//! protects data from the other hart. Nested critical sections on the same
+//! hart do not take the lock again.
+
+use crate::multicore::hart_id;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use critical_section::RawRestoreState;
+use riscv::interrupt;
+use riscv::register::mstatus;
+
+/// Test-and-set lock, taken with `amoswap.w`, one if held
+///
+/// A word rather than an `AtomicBool`, whose swap is a masked `lr.w`/`sc.w`
+/// loop on harts without byte-sized AMOs.
+static LOCKED: AtomicU32 = AtomicU32::new(0);
+/// Hart ID plus one of the lock holder, zero if not held
+static OWNER: AtomicUsize = AtomicUsize::new(0);
+
+/// Restore state bit: machine interrupts were enabled before acquiring
+const STATE_MIE: RawRestoreState = 0b01;
+/// Restore state bit: this is the outermost critical section, holding the lock
+const STATE_LOCKED: RawRestoreState = 0b10;
+
+struct DualHartCriticalSection;
+critical_section::set_impl!(DualHartCriticalSection);
+
+unsafe impl critical_section::Impl for DualHartCriticalSection {
+    unsafe fn acquire() -> RawRestoreState {
+        let mie = mstatus::read().mie();
+        interrupt::disable();
+        let mut state = if mie { STATE_MIE } else { 0 };
+        let owner = hart_id() + 1;
+        // only this hart can have written its own ID, so no race here
+        if OWNER.load(Ordering::Relaxed) != owner {
+            while LOCKED.swap(1, Ordering::Acquire) != 0 {
+                core::hint::spin_loop();
+            }
+            OWNER.store(owner, Ordering::Relaxed);
+            state |= STATE_LOCKED;
+        }
+        state
+    }
+
+    unsafe fn release(state: RawRestoreState) {
+        if state & STATE_LOCKED != 0 {
+            OWNER.store(0, Ordering::Relaxed);
+            LOCKED.store(0, Ordering::Release);
+        }
+        if state & STATE_MIE != 0 {
+            interrupt::enable();
+        }
+    }
+}
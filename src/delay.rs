@@ -0,0 +1,113 @@
+//! Delays
+//!
+//! [`Delay`] counts CLINT `mtime` ticks, which run at CPU frequency / 50 and
+//! are shared by both harts. [`McycleDelay`] counts CPU cycles of the current
+//! hart, for delays shorter than a microsecond.
+
+use crate::clint::mtime;
+use crate::clock::{Clocks, Retime};
+use embedded_hal::delay::DelayNs;
+use riscv::register::mcycle;
+
+/// Number of ticks of a counter at freq for ns nanoseconds, rounded up
+fn ticks(freq: u32, ns: u64) -> u64 {
+    // u64 overflows for long delays at high frequencies, e.g. 400 MHz over 46 s
+    (freq as u128 * ns as u128).div_ceil(1_000_000_000) as u64
+}
+
+/// Spin until counter has advanced by ticks, counting across wrap-around
+fn spin(counter: impl Fn() -> u64, ticks: u64) {
+    let start = counter();
+    while counter().wrapping_sub(start) < ticks {
+        core::hint::spin_loop();
+    }
+}
+
+/// Delay based on CLINT `mtime`
+#[derive(Clone, Copy)]
+pub struct Delay {
+    freq: u32,
+}
+
+impl Delay {
+    /// Creates a delay from the frozen clock frequencies
+    pub fn new(clocks: &Clocks) -> Self {
+        Delay {
+            freq: clocks.mtime().0,
+        }
+    }
+}
+
+impl DelayNs for Delay {
+    fn delay_ns(&mut self, ns: u32) {
+        spin(mtime::read, ticks(self.freq, ns as u64));
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        spin(mtime::read, ticks(self.freq, us as u64 * 1_000));
+    }
+
+    fn delay_ms(&mut self, ms: u32) {
+        spin(mtime::read, ticks(self.freq, ms as u64 * 1_000_000));
+    }
+}
+
+impl Retime for Delay {
+    fn retime(&mut self, clocks: &Clocks) {
+        self.freq = clocks.mtime().0;
+    }
+}
+
+/// Delay based on the `mcycle` CSR of the current hart
+///
+/// Its resolution is one CPU cycle, against 50 cycles for [`Delay`].
+#[derive(Clone, Copy)]
+pub struct McycleDelay {
+    freq: u32,
+}
+
+impl McycleDelay {
+    /// Creates a delay from the frozen clock frequencies
+    pub fn new(clocks: &Clocks) -> Self {
+        McycleDelay {
+            freq: clocks.cpu().0,
+        }
+    }
+}
+
+fn read_mcycle() -> u64 {
+    mcycle::read() as u64
+}
+
+impl DelayNs for McycleDelay {
+    fn delay_ns(&mut self, ns: u32) {
+        spin(read_mcycle, ticks(self.freq, ns as u64));
+    }
+
+    fn delay_us(&mut self, us: u32) {
+        spin(read_mcycle, ticks(self.freq, us as u64 * 1_000));
+    }
+
+    fn delay_ms(&mut self, ms: u32) {
+        spin(read_mcycle, ticks(self.freq, ms as u64 * 1_000_000));
+    }
+}
+
+impl Retime for McycleDelay {
+    fn retime(&mut self, clocks: &Clocks) {
+        self.freq = clocks.cpu().0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ticks;
+
+    #[test]
+    fn ticks_of_longest_delay_do_not_overflow() {
+        let ns = u32::MAX as u64 * 1_000_000;
+        assert_eq!(ticks(400_000_000, ns), u32::MAX as u64 * 400_000);
+        assert_eq!(ticks(8_000_000, ns), u32::MAX as u64 * 8_000);
+        assert_eq!(ticks(400_000_000, 1), 1);
+    }
+}
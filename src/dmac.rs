@@ -1,5 +1,6 @@
 //! (TODO) Direct Memory Access Controller (DMAC)
-use crate::{pac, sysctl};
+use crate::pac;
+use crate::sysctl::{self, DmaSelect};
 
 pub fn dmac_id() -> u64 {
     unsafe { (*pac::DMAC::ptr()).id.read().bits() }
@@ -10,19 +11,51 @@ pub fn dmac_version() -> u64 {
 }
 
 pub trait DmacExt {
-    fn configure(self /* sysctl ACLK clock */) -> Dmac;
+    fn configure(self /* sysctl ACLK clock */, gate: sysctl::DMA) -> Dmac;
 }
 
 impl DmacExt for pac::DMAC {
-    fn configure(self /* sysctl ACLK clock */) -> Dmac {
+    fn configure(self /* sysctl ACLK clock */, mut gate: sysctl::DMA) -> Dmac {
         // enable
-        sysctl::clk_en_peri().modify(|_, w| w.dma_clk_en().set_bit());
-        // todo: reset
-        Dmac {} // todo
+        gate.enable();
+        gate.reset();
+        Dmac { dmac: self, gate } // todo
     }
 }
 
-pub struct Dmac {}
+pub struct Dmac {
+    dmac: pac::DMAC,
+    gate: sysctl::DMA,
+}
+
+/// DMAC channel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    Channel0 = 0,
+    Channel1 = 1,
+    Channel2 = 2,
+    Channel3 = 3,
+    Channel4 = 4,
+    Channel5 = 5,
+}
+
+impl Dmac {
+    /// Releases the DMAC peripheral and its clock gate, with the clock disabled
+    pub fn release(mut self) -> (pac::DMAC, sysctl::DMA) {
+        self.gate.disable();
+        (self.dmac, self.gate)
+    }
+
+    /// Binds a channel to the handshake request of a peripheral
+    pub fn set_channel_source(&mut self, channel: Channel, select: DmaSelect) {
+        sysctl::set_dma_select(channel as usize, select);
+    }
+
+    /// Returns the handshake request a channel is bound to
+    pub fn channel_source(&self, channel: Channel) -> Option<DmaSelect> {
+        sysctl::get_dma_select(channel as usize)
+    }
+}
 
 // pub struct C0 {
 //     // todo
@@ -12,7 +12,7 @@
 */
 
 use crate::pac::FPIOA;
-use crate::sysctl::{self, APB0};
+use crate::sysctl::{self, IoVoltage, PowerDomain, APB0};
 use core::marker::PhantomData;
 
 /// FPIOA function
@@ -35,6 +35,19 @@ pub enum Pull {
 pub trait IoPin {
     const INDEX: u8;
 
+    /// Power domain (IO bank) of this pin, for example to assert at compile time
+    /// that a pin sits in the bank wired to a 1.8V device:
+    ///
+    /// ```ignore
+    /// const _: () = assert!(matches!(Io40::<JTAG_TCLK>::POWER_DOMAIN, PowerDomain::Bank6));
+    /// ```
+    const POWER_DOMAIN: PowerDomain = PowerDomain::of_pin(Self::INDEX);
+
+    /// Return the voltage currently selected for the power domain of this pin
+    fn io_voltage(&self) -> IoVoltage {
+        sysctl::io_voltage(Self::POWER_DOMAIN)
+    }
+
     fn set_io_pull(&mut self, pull: Pull) {
         unsafe {
             let fpioa = &*FPIOA::ptr();
@@ -55,21 +68,22 @@ pub trait FpioaExt {
     /// Splits the FPIOA block into independent pins and registers
     ///
     /// todo: split sysctl into two apb's, then use the APB0 to split Fpioa
-    fn split(self, apb0: &mut APB0) -> Parts;
+    fn split(self, apb0: &mut APB0, gate: sysctl::FPIOA) -> Parts;
 }
 
 /// All I/O pins
 macro_rules! def_io_pin {
     ($($IoX: ident: ($id: expr, $iox: ident, $func: ident);)+) => {
 impl FpioaExt for FPIOA {
-    fn split(self, apb0: &mut APB0) -> Parts {
+    fn split(self, apb0: &mut APB0, mut gate: sysctl::FPIOA) -> Parts {
         // enable APB0 bus
         apb0.enable();
         // enable sysctl peripheral
-        sysctl::clk_en_peri().modify(|_r, w| w.fpioa_clk_en().set_bit());
+        gate.enable();
         // return ownership
         Parts {
             $( $iox: $IoX { _function: PhantomData }, )+
+            gate,
         }
     }
 }
@@ -79,6 +93,8 @@ pub struct Parts {
         /// Programmable I/O pin
         pub $iox: $IoX<$func>,
     )+
+    /// Clock gate and reset of FPIOA, taken over on split
+    pub gate: sysctl::FPIOA,
     // todo: tie controller Tie (force set high or low as input)
 }
 pub use io_pins::*;
@@ -10,7 +10,7 @@ use embedded_hal::digital::{ErrorType, InputPin, OutputPin, StatefulOutputPin};
 /// Extension trait to split a GPIO peripheral into independent pins
 pub trait GpioExt {
     /// Split the GPIO peripheral into parts
-    fn split(self, apb0: &mut APB0) -> Parts;
+    fn split(self, apb0: &mut APB0, gate: sysctl::GPIO) -> Parts;
 }
 
 macro_rules! def_gpio_pins {
@@ -18,14 +18,15 @@ macro_rules! def_gpio_pins {
 
 impl GpioExt for pac::GPIO {
     #[inline]
-    fn split(self, apb0: &mut APB0) -> Parts {
+    fn split(self, apb0: &mut APB0, mut gate: sysctl::GPIO) -> Parts {
         // enable APB0 bus
         apb0.enable();
         // enable sysctl peripheral
-        sysctl::clk_en_peri().modify(|_r, w| w.gpio_clk_en().set_bit());
+        gate.enable();
         // return ownership
         Parts {
             $( $gpiox: $GPIOX { _ownership: () }, )+
+            gate,
         }
     }
 }
@@ -36,6 +37,8 @@ pub struct Parts {
         /// GPIO pin
         pub $gpiox: $GPIOX,
     )+
+    /// Clock gate and reset of GPIO, taken over on split
+    pub gate: sysctl::GPIO,
 }
 
 pub use gpio_pins::*;
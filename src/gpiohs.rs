@@ -1,9 +1,10 @@
 //! High-speed GPIO peripheral (GPIOHS)
 
-use crate::bit_utils::{u32_bit_is_clear, u32_bit_is_set, u32_set_bit};
+use crate::bit_utils::{u32_bit_is_set, u32_set_bit};
+use crate::fpioa::Mode;
 use crate::pac::GPIOHS;
 use core::marker::PhantomData;
-use embedded_hal::digital::{ErrorType, InputPin, OutputPin};
+use embedded_hal::digital::{ErrorType, InputPin, OutputPin, StatefulOutputPin};
 
 // todo: verify
 
@@ -19,158 +20,291 @@ pub struct Input<MODE>(MODE);
 /// Output mode (type state)
 pub struct Output<MODE>(MODE);
 
+/// Push-pull output (type state)
+pub struct PushPull;
+
+/// Extension trait to split a GPIOHS peripheral into independent pins
 pub trait GpiohsExt {
+    /// Split the GPIOHS peripheral into parts
     fn split(self) -> Parts;
 }
 
+bitflags::bitflags! {
+    pub struct Edge: u8 {
+        const RISING =  0b00000001;
+        const FALLING = 0b00000010;
+        const HIGH =    0b00000100;
+        const LOW =     0b00001000;
+    }
+}
+
+macro_rules! def_gpiohs_pins {
+    ($($GpiohsX: ident: ($num: expr, $gpiohsx: ident, $func: ident);)+) => {
+
 impl GpiohsExt for GPIOHS {
     fn split(self) -> Parts {
         Parts {
-            gpiohs0: Gpiohs0 { _mode: PhantomData },
+            $( $gpiohsx: $GpiohsX { _mode: PhantomData }, )+
         }
     }
 }
 
+/// GPIOHS peripheral parts
 pub struct Parts {
-    pub gpiohs0: Gpiohs0<Input<Floating>>,
+    $(
+        /// GPIOHS pin
+        pub $gpiohsx: $GpiohsX<Input<Floating>>,
+    )+
 }
 
-pub struct Gpiohs0<MODE> {
-    _mode: PhantomData<MODE>,
-}
+$(
+    /// GPIOHS pin
+    pub struct $GpiohsX<MODE> {
+        _mode: PhantomData<MODE>,
+    }
+
+    impl<MODE> GpiohsIndex for $GpiohsX<MODE> {
+        type FUNC = crate::fpioa::functions::$func;
+        const INDEX: u8 = $num;
+    }
+
+    impl<MODE> $GpiohsX<MODE> {
+        #[inline]
+        pub fn into_floating_input(self) -> $GpiohsX<Input<Floating>> {
+            GPIOHS::set_output_en($num, false);
+            GPIOHS::set_input_en($num, true);
+            GPIOHS::set_pullup_en($num, false);
+            $GpiohsX { _mode: PhantomData }
+        }
+
+        #[inline]
+        pub fn into_pull_up_input(self) -> $GpiohsX<Input<PullUp>> {
+            GPIOHS::set_output_en($num, false);
+            GPIOHS::set_input_en($num, true);
+            GPIOHS::set_pullup_en($num, true);
+            $GpiohsX { _mode: PhantomData }
+        }
+
+        #[inline]
+        pub fn into_push_pull_output(self) -> $GpiohsX<Output<PushPull>> {
+            GPIOHS::set_input_en($num, false);
+            GPIOHS::set_pullup_en($num, false);
+            GPIOHS::set_output_en($num, true);
+            $GpiohsX { _mode: PhantomData }
+        }
 
-impl<MODE> Gpiohs0<MODE> {
-    #[inline]
-    pub fn into_pull_up_input(self) -> Gpiohs0<Input<PullUp>> {
-        GPIOHS::set_output_en(0, false);
-        GPIOHS::set_input_en(0, true);
-        GPIOHS::set_pullup_en(0, true);
-        Gpiohs0 { _mode: PhantomData }
+        #[inline]
+        pub fn trigger_on_edge(&mut self, edge: Edge) {
+            // clear all pending bits
+            GPIOHS::clear_rise_ip($num);
+            GPIOHS::clear_fall_ip($num);
+            GPIOHS::clear_high_ip($num);
+            GPIOHS::clear_low_ip($num);
+            // enable interrupts according to flags
+            GPIOHS::set_rise_ie($num, edge.contains(Edge::RISING));
+            GPIOHS::set_fall_ie($num, edge.contains(Edge::FALLING));
+            GPIOHS::set_high_ie($num, edge.contains(Edge::HIGH));
+            GPIOHS::set_low_ie($num, edge.contains(Edge::LOW));
+        }
+
+        #[inline]
+        pub fn check_edges(&self) -> Edge {
+            let mut ans = Edge::empty();
+            if GPIOHS::has_rise_ip($num) {
+                ans |= Edge::RISING;
+            }
+            if GPIOHS::has_fall_ip($num) {
+                ans |= Edge::FALLING;
+            }
+            if GPIOHS::has_high_ip($num) {
+                ans |= Edge::HIGH;
+            }
+            if GPIOHS::has_low_ip($num) {
+                ans |= Edge::LOW;
+            }
+            ans
+        }
+
+        #[inline]
+        pub fn clear_interrupt_pending_bits(&mut self) {
+            if GPIOHS::has_rise_ie($num) {
+                GPIOHS::set_rise_ie($num, false);
+                GPIOHS::clear_rise_ip($num);
+                GPIOHS::set_rise_ie($num, true);
+            }
+            if GPIOHS::has_fall_ie($num) {
+                GPIOHS::set_fall_ie($num, false);
+                GPIOHS::clear_fall_ip($num);
+                GPIOHS::set_fall_ie($num, true);
+            }
+            if GPIOHS::has_high_ie($num) {
+                GPIOHS::set_high_ie($num, false);
+                GPIOHS::clear_high_ip($num);
+                GPIOHS::set_high_ie($num, true);
+            }
+            if GPIOHS::has_low_ie($num) {
+                GPIOHS::set_low_ie($num, false);
+                GPIOHS::clear_low_ip($num);
+                GPIOHS::set_low_ie($num, true);
+            }
+        }
     }
 
-    // todo: all modes
-}
+    impl<MODE> ErrorType for $GpiohsX<MODE> {
+        // All GPIO operations are infallible.
+        type Error = core::convert::Infallible;
+    }
 
-bitflags::bitflags! {
-    pub struct Edge: u8 {
-        const RISING =  0b00000001;
-        const FALLING = 0b00000010;
-        const HIGH =    0b00000100;
-        const LOW =     0b00001000;
+    impl<MODE> InputPin for $GpiohsX<Input<MODE>> {
+        #[inline]
+        fn is_high(&mut self) -> Result<bool, Self::Error> {
+            Ok(GPIOHS::input_value($num))
+        }
+
+        #[inline]
+        fn is_low(&mut self) -> Result<bool, Self::Error> {
+            Ok(!GPIOHS::input_value($num))
+        }
+    }
+
+    impl<MODE> OutputPin for $GpiohsX<Output<MODE>> {
+        #[inline]
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            GPIOHS::set_output_value($num, true);
+            Ok(())
+        }
+
+        #[inline]
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            GPIOHS::set_output_value($num, false);
+            Ok(())
+        }
+    }
+
+    impl<MODE> StatefulOutputPin for $GpiohsX<Output<MODE>> {
+        #[inline]
+        fn is_set_high(&mut self) -> Result<bool, Self::Error> {
+            Ok(GPIOHS::output_value($num))
+        }
+
+        #[inline]
+        fn is_set_low(&mut self) -> Result<bool, Self::Error> {
+            Ok(!GPIOHS::output_value($num))
+        }
     }
+)+
+    };
+}
+
+def_gpiohs_pins! {
+    Gpiohs0: (0, gpiohs0, GPIOHS0);
+    Gpiohs1: (1, gpiohs1, GPIOHS1);
+    Gpiohs2: (2, gpiohs2, GPIOHS2);
+    Gpiohs3: (3, gpiohs3, GPIOHS3);
+    Gpiohs4: (4, gpiohs4, GPIOHS4);
+    Gpiohs5: (5, gpiohs5, GPIOHS5);
+    Gpiohs6: (6, gpiohs6, GPIOHS6);
+    Gpiohs7: (7, gpiohs7, GPIOHS7);
+    Gpiohs8: (8, gpiohs8, GPIOHS8);
+    Gpiohs9: (9, gpiohs9, GPIOHS9);
+    Gpiohs10: (10, gpiohs10, GPIOHS10);
+    Gpiohs11: (11, gpiohs11, GPIOHS11);
+    Gpiohs12: (12, gpiohs12, GPIOHS12);
+    Gpiohs13: (13, gpiohs13, GPIOHS13);
+    Gpiohs14: (14, gpiohs14, GPIOHS14);
+    Gpiohs15: (15, gpiohs15, GPIOHS15);
+    Gpiohs16: (16, gpiohs16, GPIOHS16);
+    Gpiohs17: (17, gpiohs17, GPIOHS17);
+    Gpiohs18: (18, gpiohs18, GPIOHS18);
+    Gpiohs19: (19, gpiohs19, GPIOHS19);
+    Gpiohs20: (20, gpiohs20, GPIOHS20);
+    Gpiohs21: (21, gpiohs21, GPIOHS21);
+    Gpiohs22: (22, gpiohs22, GPIOHS22);
+    Gpiohs23: (23, gpiohs23, GPIOHS23);
+    Gpiohs24: (24, gpiohs24, GPIOHS24);
+    Gpiohs25: (25, gpiohs25, GPIOHS25);
+    Gpiohs26: (26, gpiohs26, GPIOHS26);
+    Gpiohs27: (27, gpiohs27, GPIOHS27);
+    Gpiohs28: (28, gpiohs28, GPIOHS28);
+    Gpiohs29: (29, gpiohs29, GPIOHS29);
+    Gpiohs30: (30, gpiohs30, GPIOHS30);
+    Gpiohs31: (31, gpiohs31, GPIOHS31);
+}
+
+/// GPIOHS Index
+pub trait GpiohsIndex {
+    type FUNC;
+    const INDEX: u8;
 }
 
-impl<MODE> Gpiohs0<MODE> {
+/// GPIOHS pin paired with the IO pin it is routed to
+pub struct Gpiohs<GPIOHS, PIN> {
+    gpiohs: GPIOHS,
+    pin: PIN,
+}
+
+impl<GPIOHS: GpiohsIndex, PIN: Mode<GPIOHS::FUNC>> Gpiohs<GPIOHS, PIN> {
     #[inline]
-    pub fn trigger_on_edge(&mut self, edge: Edge) {
-        // clear all pending bits
-        GPIOHS::clear_rise_ip(0);
-        GPIOHS::clear_fall_ip(0);
-        GPIOHS::clear_high_ip(0);
-        GPIOHS::clear_low_ip(0);
-        // enable interrupts according to flags
-        GPIOHS::set_rise_ie(0, edge.contains(Edge::RISING));
-        GPIOHS::set_fall_ie(0, edge.contains(Edge::FALLING));
-        GPIOHS::set_high_ie(0, edge.contains(Edge::HIGH));
-        GPIOHS::set_low_ie(0, edge.contains(Edge::LOW));
+    pub fn new(gpiohs: GPIOHS, pin: PIN) -> Gpiohs<GPIOHS, PIN> {
+        Gpiohs { gpiohs, pin }
     }
+}
 
+impl<GPIOHS, PIN> Gpiohs<GPIOHS, PIN> {
     #[inline]
-    pub fn check_edges(&self) -> Edge {
-        let mut ans = Edge::empty();
-        if GPIOHS::has_rise_ip(0) {
-            ans |= Edge::RISING;
-        }
-        if GPIOHS::has_fall_ip(0) {
-            ans |= Edge::FALLING;
-        }
-        if GPIOHS::has_high_ip(0) {
-            ans |= Edge::HIGH;
-        }
-        if GPIOHS::has_low_ip(0) {
-            ans |= Edge::LOW;
-        }
-        ans
+    pub fn free(self) -> (GPIOHS, PIN) {
+        (self.gpiohs, self.pin)
     }
 
+    /// The GPIOHS pin, to change its mode or interrupts
     #[inline]
-    pub fn clear_interrupt_pending_bits(&mut self) {
-        if GPIOHS::has_rise_ie(0) {
-            GPIOHS::set_rise_ie(0, false);
-            GPIOHS::clear_rise_ip(0);
-            GPIOHS::set_rise_ie(0, true);
-        }
-        if GPIOHS::has_fall_ie(0) {
-            GPIOHS::set_fall_ie(0, false);
-            GPIOHS::clear_fall_ip(0);
-            GPIOHS::set_fall_ie(0, true);
-        }
-        if GPIOHS::has_high_ie(0) {
-            GPIOHS::set_high_ie(0, false);
-            GPIOHS::clear_high_ip(0);
-            GPIOHS::set_high_ie(0, true);
-        }
-        if GPIOHS::has_low_ie(0) {
-            GPIOHS::set_low_ie(0, false);
-            GPIOHS::clear_low_ip(0);
-            GPIOHS::set_low_ie(0, true);
-        }
+    pub fn gpiohs(&mut self) -> &mut GPIOHS {
+        &mut self.gpiohs
     }
 }
 
-impl<MODE> ErrorType for Gpiohs0<MODE> {
-    // All GPIO operations are infallible.
-    type Error = core::convert::Infallible;
+impl<GPIOHS: ErrorType, PIN> ErrorType for Gpiohs<GPIOHS, PIN> {
+    type Error = GPIOHS::Error;
 }
 
-impl<MODE> InputPin for Gpiohs0<Input<MODE>> {
+impl<GPIOHS: InputPin, PIN> InputPin for Gpiohs<GPIOHS, PIN> {
     #[inline]
     fn is_high(&mut self) -> Result<bool, Self::Error> {
-        Ok(unsafe {
-            let p = &(*GPIOHS::ptr()).input_val as *const _ as *const _;
-            u32_bit_is_set(p, 0)
-        })
+        self.gpiohs.is_high()
     }
 
     #[inline]
     fn is_low(&mut self) -> Result<bool, Self::Error> {
-        Ok(unsafe {
-            let p = &(*GPIOHS::ptr()).input_val as *const _ as *const _;
-            u32_bit_is_clear(p, 0)
-        })
+        self.gpiohs.is_low()
     }
 }
 
-impl<MODE> OutputPin for Gpiohs0<Output<MODE>> {
+impl<GPIOHS: OutputPin, PIN> OutputPin for Gpiohs<GPIOHS, PIN> {
     #[inline]
     fn set_high(&mut self) -> Result<(), Self::Error> {
-        unsafe {
-            let p = &(*GPIOHS::ptr()).output_val as *const _ as *mut _;
-            u32_set_bit(p, true, 0);
-        }
-        Ok(())
+        self.gpiohs.set_high()
     }
 
     #[inline]
     fn set_low(&mut self) -> Result<(), Self::Error> {
-        unsafe {
-            let p = &(*GPIOHS::ptr()).output_val as *const _ as *mut _;
-            u32_set_bit(p, false, 0);
-        }
-        Ok(())
+        self.gpiohs.set_low()
     }
 }
 
-trait GpiohsAccess {
-    fn peripheral() -> &'static mut crate::pac::gpiohs::RegisterBlock;
+impl<GPIOHS: StatefulOutputPin, PIN> StatefulOutputPin for Gpiohs<GPIOHS, PIN> {
+    #[inline]
+    fn is_set_high(&mut self) -> Result<bool, Self::Error> {
+        self.gpiohs.is_set_high()
+    }
 
     #[inline]
-    fn set_drive(index: usize, bit: bool) {
-        unsafe {
-            let p = &mut Self::peripheral().drive as *mut _ as *mut _;
-            u32_set_bit(p, bit, index);
-        }
+    fn is_set_low(&mut self) -> Result<bool, Self::Error> {
+        self.gpiohs.is_set_low()
     }
+}
+
+trait GpiohsAccess {
+    fn peripheral() -> &'static mut crate::pac::gpiohs::RegisterBlock;
 
     #[inline]
     fn input_value(index: usize) -> bool {
@@ -188,22 +322,6 @@ trait GpiohsAccess {
         }
     }
 
-    #[inline]
-    fn set_iof_en(index: usize, bit: bool) {
-        unsafe {
-            let p = &mut Self::peripheral().iof_en as *mut _ as *mut _;
-            u32_set_bit(p, bit, index);
-        }
-    }
-
-    #[inline]
-    fn set_iof_sel(index: usize, bit: bool) {
-        unsafe {
-            let p = &mut Self::peripheral().iof_sel as *mut _ as *mut _;
-            u32_set_bit(p, bit, index);
-        }
-    }
-
     #[inline]
     fn set_output_en(index: usize, bit: bool) {
         unsafe {
@@ -221,10 +339,10 @@ trait GpiohsAccess {
     }
 
     #[inline]
-    fn set_output_xor(index: usize, bit: bool) {
+    fn output_value(index: usize) -> bool {
         unsafe {
-            let p = &mut Self::peripheral().output_xor as *mut _ as *mut _;
-            u32_set_bit(p, bit, index);
+            let p = &mut Self::peripheral().output_val as *mut _ as *mut _;
+            u32_bit_is_set(p, index)
         }
     }
 
@@ -12,11 +12,15 @@ pub mod apu;
 pub mod cache;
 pub mod clint;
 pub mod clock;
+#[cfg(feature = "critical-section-impl")]
+mod critical_section_impl;
+pub mod delay;
 pub mod dmac;
 pub mod fft;
 pub mod fpioa;
 pub mod gpio;
 pub mod gpiohs;
+pub mod multicore;
 pub mod plic;
 pub mod serial;
 pub mod sha256;
@@ -0,0 +1,112 @@
+//! Dual-core support
+//!
+//! Hart 1 is parked by calling `park_hart1` early in its boot path, for
+//! example from the `_mp_hook` of `riscv-rt`; it is only available when
+//! building for the K210 itself. Hart 0 later hands it a stack
+//! and an entry point with [`start_hart1`]:
+//!
+//! ```ignore
+//! static mut STACK1: [u8; 16 * 1024] = [0; 16 * 1024];
+//!
+//! fn main_hart1() -> ! {
+//!     loop {}
+//! }
+//!
+//! multicore::start_hart1(unsafe { &mut *core::ptr::addr_of_mut!(STACK1) }, main_hart1);
+//! ```
+
+use crate::clint::msip;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use riscv::register::mhartid;
+
+/// Number of harts of the K210
+pub const HART_COUNT: usize = 2;
+
+static HART1_STARTED: AtomicBool = AtomicBool::new(false);
+/// Entry point of hart 1, zero until it is started
+static HART1_ENTRY: AtomicUsize = AtomicUsize::new(0);
+static HART1_STACK_TOP: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the ID of the current hart
+#[inline]
+pub fn hart_id() -> usize {
+    mhartid::read()
+}
+
+/// Runs entry on hart 1 with the given stack
+///
+/// Hart 1 must be waiting in `park_hart1`.
+///
+/// # Panics
+///
+/// Panics if hart 1 was started before.
+pub fn start_hart1(stack: &'static mut [u8], entry: fn() -> !) {
+    if HART1_STARTED.swap(true, Ordering::AcqRel) {
+        panic!("hart 1 is already started");
+    }
+    // the stack grows downwards from its 16-byte aligned end
+    let stack_top = (stack.as_mut_ptr() as usize + stack.len()) & !0xf;
+    HART1_STACK_TOP.store(stack_top, Ordering::Relaxed);
+    HART1_ENTRY.store(entry as usize, Ordering::Release);
+    // hart 1 reads the entry point once woken
+    msip::fence_w_o();
+    msip::set_ipi(1);
+}
+
+/// Parks hart 1 in a `wfi` loop until [`start_hart1`] hands it an entry point
+///
+/// # Safety
+///
+/// Must be called on hart 1 only, before it uses its boot stack for anything
+/// that has to outlive this call: the stack pointer is switched to the one
+/// given by `start_hart1`.
+#[cfg(target_arch = "riscv64")]
+pub unsafe fn park_hart1() -> ! {
+    use riscv::register::mie;
+    mie::set_msoft();
+    let entry = loop {
+        match HART1_ENTRY.load(Ordering::Acquire) {
+            0 => riscv::asm::wfi(),
+            entry => break entry,
+        }
+    };
+    msip::clear_ipi(1);
+    mie::clear_msoft();
+    let stack_top = HART1_STACK_TOP.load(Ordering::Relaxed);
+    jump(stack_top, entry)
+}
+
+#[cfg(target_arch = "riscv64")]
+unsafe fn jump(stack_top: usize, entry: usize) -> ! {
+    core::arch::asm!(
+        "mv sp, {stack_top}",
+        "jr {entry}",
+        stack_top = in(reg) stack_top,
+        entry = in(reg) entry,
+        options(noreturn),
+    )
+}
+
+/// Storage with one value per hart
+///
+/// Each hart only ever sees its own value, much like a thread local. Interrupt
+/// handlers preempt the program on the same hart, so a static one needs a
+/// `Sync` value, e.g. an atomic or a critical section mutex.
+pub struct HartLocal<T> {
+    values: [T; HART_COUNT],
+}
+
+impl<T> HartLocal<T> {
+    /// Creates the storage from the values of hart 0 and hart 1
+    pub const fn new(hart0: T, hart1: T) -> Self {
+        HartLocal {
+            values: [hart0, hart1],
+        }
+    }
+
+    /// Returns the value of the current hart
+    #[inline]
+    pub fn get(&self) -> &T {
+        &self.values[hart_id()]
+    }
+}
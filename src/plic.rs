@@ -1,29 +1,25 @@
 //! Platform-Level Interrupt Controller (PLIC)
+//!
+//! Besides register access through [`PlicExt`], the [`dispatch`] module
+//! routes external interrupts to registered handlers.
 
-// I don't know if this part should be implemented in runtime
-// keep by now, may be replaced on further designs
-// #[doc(hidden)]
-// #[link_name = "MachineExternal"]
-// pub extern fn machine_external() {
-
-// }
-
+use crate::multicore::HART_COUNT;
 use crate::pac::{Interrupt, PLIC};
 
 /// Extension trait for PLIC interrupt controller peripheral
 pub trait PlicExt {
     /// Interrupt wrapper type
     type Interrupt: Nr;
-    /// Is this M-Mode interrupt enabled on given hart?
-    fn is_enabled(hart_id: usize, interrupt: Self::Interrupt) -> bool;
-    /// Enable an interrupt for a given hart
+    /// Is this interrupt enabled on given context?
+    fn is_enabled(context: Context, interrupt: Self::Interrupt) -> bool;
+    /// Enable an interrupt for a given context
     ///
     /// # Safety
     ///
     /// May effect normal interrupt handling procedure
-    unsafe fn unmask(hart_id: usize, interrupt: Self::Interrupt);
-    /// Disable an interrupt for a given hart
-    fn mask(hart_id: usize, interrupt: Self::Interrupt);
+    unsafe fn unmask(context: Context, interrupt: Self::Interrupt);
+    /// Disable an interrupt for a given context
+    fn mask(context: Context, interrupt: Self::Interrupt);
     /// Get global priority for one interrupt
     fn get_priority(interrupt: Self::Interrupt) -> Priority;
     /// Globally set priority for one interrupt
@@ -32,71 +28,99 @@ pub trait PlicExt {
     ///
     /// May effect normal interrupt handling procedure
     unsafe fn set_priority(interrupt: Self::Interrupt, prio: Priority);
-    /// Get priority threshold for a given hart
-    fn get_threshold(hart_id: usize) -> Priority;
-    /// Set the priority threshold for a given hart
+    /// Get priority threshold for a given context
+    fn get_threshold(context: Context) -> Priority;
+    /// Set the priority threshold for a given context
     ///
     /// # Safety
     ///
     /// May effect normal interrupt handling procedure
-    unsafe fn set_threshold(hart_id: usize, threshold: Priority);
-    /// Mark that given hart have claimed to handle this interrupt
-    fn claim(hart_id: usize) -> Option<Self::Interrupt>;
-    /// Mark that given hart have completed handling this interrupt
-    fn complete(hart_id: usize, interrupt: Self::Interrupt);
+    unsafe fn set_threshold(context: Context, threshold: Priority);
+    /// Mark that given context have claimed to handle this interrupt
+    ///
+    /// Returns `Ok(None)` if no interrupt is pending. A claimed source which is
+    /// not a known interrupt is completed right away and reported as an error.
+    /// The returned guard completes the interrupt when dropped.
+    fn claim(context: Context) -> Result<Option<Claimed<Self::Interrupt>>, UnknownInterrupt>;
+    /// Mark that given context have completed handling this interrupt
+    fn complete(context: Context, interrupt: Self::Interrupt);
     /// Is this interrupt claimed and under procceeding?
     fn is_pending(interrupt: Self::Interrupt) -> bool;
+    /// Route an interrupt to the given contexts only
+    ///
+    /// The interrupt is enabled on every listed context before it is masked
+    /// on the others, so it is never left without a target while moving
+    /// between harts. Local interrupts are disabled meanwhile.
+    ///
+    /// # Safety
+    ///
+    /// May effect normal interrupt handling procedure
+    unsafe fn set_affinity(interrupt: Self::Interrupt, contexts: &[Context]);
 }
 
 impl PlicExt for PLIC {
     type Interrupt = Interrupt;
-    fn is_enabled(hart_id: usize, interrupt: Interrupt) -> bool {
+    fn is_enabled(context: Context, interrupt: Interrupt) -> bool {
         let irq_number = interrupt.into_bits() as usize;
         unsafe {
-            (*PLIC::ptr()).target_enables[hart_id].enable[irq_number / 32]
+            (*PLIC::ptr()).target_enables[context.index()].enable[irq_number / 32]
                 .read()
                 .bits()
                 & 1 << (irq_number % 32)
                 != 0
         }
     }
-    unsafe fn unmask(hart_id: usize, interrupt: Interrupt) {
+    unsafe fn unmask(context: Context, interrupt: Interrupt) {
         let irq_number = interrupt.into_bits() as usize;
-        (*PLIC::ptr()).target_enables[hart_id].enable[irq_number / 32]
+        (*PLIC::ptr()).target_enables[context.index()].enable[irq_number / 32]
             .modify(|r, w| w.bits(r.bits() | 1 << (irq_number % 32)));
     }
-    fn mask(hart_id: usize, interrupt: Interrupt) {
+    fn mask(context: Context, interrupt: Interrupt) {
         let irq_number = interrupt.into_bits() as usize;
         unsafe {
-            (*PLIC::ptr()).target_enables[hart_id].enable[irq_number / 32]
+            (*PLIC::ptr()).target_enables[context.index()].enable[irq_number / 32]
                 .modify(|r, w| w.bits(r.bits() & !(1 << (irq_number % 32))));
         }
     }
     fn get_priority(interrupt: Interrupt) -> Priority {
         let irq_number = interrupt.into_bits() as usize;
         let bits = unsafe { (*PLIC::ptr()).priority[irq_number].read().bits() };
-        Priority::from_bits(bits)
+        Priority::from_register(bits)
     }
     unsafe fn set_priority(interrupt: Interrupt, prio: Priority) {
         let irq_number = interrupt.into_bits() as usize;
         (*PLIC::ptr()).priority[irq_number].write(|w| w.bits(prio.into_bits()));
     }
-    fn get_threshold(hart_id: usize) -> Priority {
-        let bits = unsafe { (*PLIC::ptr()).targets[hart_id].threshold.read().bits() };
-        Priority::from_bits(bits)
+    fn get_threshold(context: Context) -> Priority {
+        let bits = unsafe {
+            (*PLIC::ptr()).targets[context.index()]
+                .threshold
+                .read()
+                .bits()
+        };
+        Priority::from_register(bits)
     }
-    unsafe fn set_threshold(hart_id: usize, threshold: Priority) {
-        (*PLIC::ptr()).targets[hart_id]
+    unsafe fn set_threshold(context: Context, threshold: Priority) {
+        (*PLIC::ptr()).targets[context.index()]
             .threshold
             .write(|w| w.bits(threshold.into_bits()));
     }
-    fn claim(hart_id: usize) -> Option<Interrupt> {
-        let bits = unsafe { (*PLIC::ptr()).targets[hart_id].claim.read().bits() };
-        Nr::from_bits(bits)
+    fn claim(context: Context) -> Result<Option<Claimed<Interrupt>>, UnknownInterrupt> {
+        let claim = unsafe { &(*PLIC::ptr()).targets[context.index()].claim };
+        match claim.read().bits() {
+            0 => Ok(None),
+            bits => match Interrupt::from_bits(bits) {
+                Ok(interrupt) => Ok(Some(Claimed { interrupt, context })),
+                Err(unknown) => {
+                    unsafe { claim.write(|w| w.bits(bits)) };
+                    Err(unknown)
+                }
+            },
+        }
     }
-    fn complete(hart_id: usize, interrupt: Interrupt) {
+    fn complete(context: Context, interrupt: Interrupt) {
         unsafe {
-            (*PLIC::ptr()).targets[hart_id]
+            (*PLIC::ptr()).targets[context.index()]
                 .claim
                 .write(|w| w.bits(interrupt.into_bits()));
         }
@@ -107,82 +131,203 @@ impl PlicExt for PLIC {
             (*PLIC::ptr()).pending[irq_number / 32].read().bits() & 1 << (irq_number % 32) != 0
         }
     }
+    unsafe fn set_affinity(interrupt: Interrupt, contexts: &[Context]) {
+        riscv::interrupt::free(|_| {
+            for &context in contexts {
+                PLIC::unmask(context, interrupt);
+            }
+            for context in Context::all() {
+                if !contexts.contains(&context) {
+                    PLIC::mask(context, interrupt);
+                }
+            }
+        })
+    }
+}
+
+/// Privilege mode of a PLIC context
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Privilege {
+    /// Machine mode
+    Machine,
+    /// Supervisor mode
+    Supervisor,
+}
+
+/// Interrupt target of the PLIC, one hart in one privilege mode
+///
+/// Each hart has a machine mode and a supervisor mode context; supervisor
+/// mode kernels claim their interrupts through the latter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Context {
+    hart_id: usize,
+    privilege: Privilege,
+}
+
+impl Context {
+    /// Context of a hart in the given privilege mode
+    ///
+    /// # Panics
+    ///
+    /// Panics if the hart does not exist.
+    pub const fn new(hart_id: usize, privilege: Privilege) -> Context {
+        assert!(hart_id < HART_COUNT, "no such hart");
+        Context { hart_id, privilege }
+    }
+    /// Machine mode context of a hart
+    pub const fn machine(hart_id: usize) -> Context {
+        Context::new(hart_id, Privilege::Machine)
+    }
+    /// Supervisor mode context of a hart
+    pub const fn supervisor(hart_id: usize) -> Context {
+        Context::new(hart_id, Privilege::Supervisor)
+    }
+    /// Hart of this context
+    pub const fn hart_id(self) -> usize {
+        self.hart_id
+    }
+    /// Privilege mode of this context
+    pub const fn privilege(self) -> Privilege {
+        self.privilege
+    }
+    /// All contexts, ordered by their register index
+    pub fn all() -> impl Iterator<Item = Context> {
+        (0..HART_COUNT)
+            .flat_map(|hart_id| [Context::machine(hart_id), Context::supervisor(hart_id)])
+    }
+    /// Index into the per-context registers
+    fn index(self) -> usize {
+        self.hart_id * 2
+            + match self.privilege {
+                Privilege::Machine => 0,
+                Privilege::Supervisor => 1,
+            }
+    }
+}
+
+/// Interrupt claimed by a context, completed when dropped
+///
+/// Completing on drop keeps the source from staying claimed after an early
+/// return or a panic in the handler.
+#[must_use = "dropping the guard completes the interrupt right away"]
+#[derive(Debug)]
+pub struct Claimed<I: Nr> {
+    interrupt: I,
+    context: Context,
+}
+
+impl<I: Nr> Claimed<I> {
+    /// The claimed interrupt
+    pub fn interrupt(&self) -> I {
+        self.interrupt
+    }
+    /// The context which claimed the interrupt
+    pub fn context(&self) -> Context {
+        self.context
+    }
+    /// The hart which claimed the interrupt
+    pub fn hart_id(&self) -> usize {
+        self.context.hart_id()
+    }
+    /// Complete the interrupt now
+    pub fn complete(self) {
+        drop(self)
+    }
+    /// Release the guard without completing the interrupt
+    ///
+    /// The source stays claimed until `PlicExt::complete` is called with the
+    /// returned interrupt on the same context.
+    pub fn forget(self) -> I {
+        let interrupt = self.interrupt;
+        core::mem::forget(self);
+        interrupt
+    }
+}
+
+impl<I: Nr> Drop for Claimed<I> {
+    fn drop(&mut self) {
+        // note(unsafe): writing back a claimed id only completes that claim
+        unsafe {
+            (*PLIC::ptr()).targets[self.context.index()]
+                .claim
+                .write(|w| w.bits(self.interrupt.into_bits()));
+        }
+    }
 }
 
 impl Nr for Interrupt {
     fn into_bits(self) -> u32 {
         self as u8 as u32
     }
-    fn from_bits(bits: u32) -> Option<Self> {
+    fn from_bits(bits: u32) -> Result<Self, UnknownInterrupt> {
         use Interrupt::*;
         match bits {
-            0 => None,
-            1 => Some(SPI0),
-            2 => Some(SPI1),
-            3 => Some(SPI_SLAVE),
-            4 => Some(SPI3),
-            5 => Some(I2S0),
-            6 => Some(I2S1),
-            7 => Some(I2S2),
-            8 => Some(I2C0),
-            9 => Some(I2C1),
-            10 => Some(I2C2),
-            11 => Some(UART1),
-            12 => Some(UART2),
-            13 => Some(UART3),
-            14 => Some(TIMER0A),
-            15 => Some(TIMER0B),
-            16 => Some(TIMER1A),
-            17 => Some(TIMER1B),
-            18 => Some(TIMER2A),
-            19 => Some(TIMER2B),
-            20 => Some(RTC),
-            21 => Some(WDT0),
-            22 => Some(WDT1),
-            23 => Some(APB_GPIO),
-            24 => Some(DVP),
-            25 => Some(KPU),
-            26 => Some(FFT),
-            27 => Some(DMA0),
-            28 => Some(DMA1),
-            29 => Some(DMA2),
-            30 => Some(DMA3),
-            31 => Some(DMA4),
-            32 => Some(DMA5),
-            33 => Some(UARTHS),
-            34 => Some(GPIOHS0),
-            35 => Some(GPIOHS1),
-            36 => Some(GPIOHS2),
-            37 => Some(GPIOHS3),
-            38 => Some(GPIOHS4),
-            39 => Some(GPIOHS5),
-            40 => Some(GPIOHS6),
-            41 => Some(GPIOHS7),
-            42 => Some(GPIOHS8),
-            43 => Some(GPIOHS9),
-            44 => Some(GPIOHS10),
-            45 => Some(GPIOHS11),
-            46 => Some(GPIOHS12),
-            47 => Some(GPIOHS13),
-            48 => Some(GPIOHS14),
-            49 => Some(GPIOHS15),
-            50 => Some(GPIOHS16),
-            51 => Some(GPIOHS17),
-            52 => Some(GPIOHS18),
-            53 => Some(GPIOHS19),
-            54 => Some(GPIOHS20),
-            55 => Some(GPIOHS21),
-            56 => Some(GPIOHS22),
-            57 => Some(GPIOHS23),
-            58 => Some(GPIOHS24),
-            59 => Some(GPIOHS25),
-            60 => Some(GPIOHS26),
-            61 => Some(GPIOHS27),
-            62 => Some(GPIOHS28),
-            63 => Some(GPIOHS29),
-            64 => Some(GPIOHS30),
-            65 => Some(GPIOHS31),
-            _ => panic!("invalid interrupt bits"),
+            1 => Ok(SPI0),
+            2 => Ok(SPI1),
+            3 => Ok(SPI_SLAVE),
+            4 => Ok(SPI3),
+            5 => Ok(I2S0),
+            6 => Ok(I2S1),
+            7 => Ok(I2S2),
+            8 => Ok(I2C0),
+            9 => Ok(I2C1),
+            10 => Ok(I2C2),
+            11 => Ok(UART1),
+            12 => Ok(UART2),
+            13 => Ok(UART3),
+            14 => Ok(TIMER0A),
+            15 => Ok(TIMER0B),
+            16 => Ok(TIMER1A),
+            17 => Ok(TIMER1B),
+            18 => Ok(TIMER2A),
+            19 => Ok(TIMER2B),
+            20 => Ok(RTC),
+            21 => Ok(WDT0),
+            22 => Ok(WDT1),
+            23 => Ok(APB_GPIO),
+            24 => Ok(DVP),
+            25 => Ok(KPU),
+            26 => Ok(FFT),
+            27 => Ok(DMA0),
+            28 => Ok(DMA1),
+            29 => Ok(DMA2),
+            30 => Ok(DMA3),
+            31 => Ok(DMA4),
+            32 => Ok(DMA5),
+            33 => Ok(UARTHS),
+            34 => Ok(GPIOHS0),
+            35 => Ok(GPIOHS1),
+            36 => Ok(GPIOHS2),
+            37 => Ok(GPIOHS3),
+            38 => Ok(GPIOHS4),
+            39 => Ok(GPIOHS5),
+            40 => Ok(GPIOHS6),
+            41 => Ok(GPIOHS7),
+            42 => Ok(GPIOHS8),
+            43 => Ok(GPIOHS9),
+            44 => Ok(GPIOHS10),
+            45 => Ok(GPIOHS11),
+            46 => Ok(GPIOHS12),
+            47 => Ok(GPIOHS13),
+            48 => Ok(GPIOHS14),
+            49 => Ok(GPIOHS15),
+            50 => Ok(GPIOHS16),
+            51 => Ok(GPIOHS17),
+            52 => Ok(GPIOHS18),
+            53 => Ok(GPIOHS19),
+            54 => Ok(GPIOHS20),
+            55 => Ok(GPIOHS21),
+            56 => Ok(GPIOHS22),
+            57 => Ok(GPIOHS23),
+            58 => Ok(GPIOHS24),
+            59 => Ok(GPIOHS25),
+            60 => Ok(GPIOHS26),
+            61 => Ok(GPIOHS27),
+            62 => Ok(GPIOHS28),
+            63 => Ok(GPIOHS29),
+            64 => Ok(GPIOHS30),
+            65 => Ok(GPIOHS31),
+            _ => Err(UnknownInterrupt(bits)),
         }
     }
 }
@@ -190,11 +335,21 @@ impl Nr for Interrupt {
 #[doc(hidden)]
 pub trait Nr: Sized + Copy {
     fn into_bits(self) -> u32;
-    fn from_bits(bits: u32) -> Option<Self>;
+    fn from_bits(bits: u32) -> Result<Self, UnknownInterrupt>;
 }
 
+/// Interrupt source number that does not match any known interrupt
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnknownInterrupt(pub u32);
+
+/// Priority value above the highest priority, P7
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidPriority(pub u32);
+
 /// Priority of an interrupt
-#[derive(Clone, Copy, Debug)]
+///
+/// Priorities compare by level, so thresholds can be checked with `<` and `>`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Priority {
     /// Priority 0: Never interrupt
     P0,
@@ -227,8 +382,9 @@ impl Priority {
             Priority::P7 => 7,
         }
     }
-    fn from_bits(prio: u32) -> Priority {
-        match prio {
+    /// Priority from a 3-bit register field, ignoring upper bits
+    fn from_register(bits: u32) -> Priority {
+        match bits & 0b111 {
             0 => Priority::P0,
             1 => Priority::P1,
             2 => Priority::P2,
@@ -236,8 +392,148 @@ impl Priority {
             4 => Priority::P4,
             5 => Priority::P5,
             6 => Priority::P6,
-            7 => Priority::P7,
-            _ => panic!("Invalid priority"),
+            _ => Priority::P7,
+        }
+    }
+}
+
+impl TryFrom<u32> for Priority {
+    type Error = InvalidPriority;
+
+    fn try_from(prio: u32) -> Result<Priority, InvalidPriority> {
+        match prio {
+            0..=7 => Ok(Priority::from_register(prio)),
+            _ => Err(InvalidPriority(prio)),
+        }
+    }
+}
+
+impl From<Priority> for u32 {
+    fn from(prio: Priority) -> u32 {
+        prio.into_bits()
+    }
+}
+
+/// Handler table for external interrupts
+///
+/// Register handlers, then forward the machine external interrupt of each
+/// hart to [`dispatch::machine_external`], or to
+/// [`dispatch::machine_external_nested`] to let higher priority interrupts
+/// preempt running handlers:
+///
+/// ```ignore
+/// unsafe { dispatch::register(Interrupt::UARTHS, on_uarths, Priority::P1, 0) };
+///
+/// #[no_mangle]
+/// fn MachineExternal() {
+///     plic::dispatch::machine_external();
+/// }
+/// ```
+pub mod dispatch {
+    use super::{Context, Nr, PlicExt, Priority};
+    use crate::multicore::hart_id;
+    use crate::pac::{Interrupt, PLIC};
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use riscv::interrupt;
+    use riscv::register::{mepc, mstatus};
+
+    /// Number of PLIC interrupt sources, including the reserved source 0
+    pub const SOURCES: usize = 66;
+
+    /// Handler addresses by interrupt number, zero if none
+    static HANDLERS: [AtomicUsize; SOURCES] = [const { AtomicUsize::new(0) }; SOURCES];
+
+    /// Registers the handler of an interrupt and enables it on a hart
+    ///
+    /// The handler is shared by both harts, so registering the same interrupt
+    /// on another hart replaces the handler for both.
+    ///
+    /// # Safety
+    ///
+    /// May effect normal interrupt handling procedure, see `PlicExt::unmask`
+    pub unsafe fn register(
+        interrupt: Interrupt,
+        handler: fn(),
+        priority: Priority,
+        hart_id: usize,
+    ) {
+        HANDLERS[interrupt.into_bits() as usize].store(handler as usize, Ordering::Release);
+        PLIC::set_priority(interrupt, priority);
+        PLIC::unmask(Context::machine(hart_id), interrupt);
+    }
+
+    /// Disables an interrupt on a hart and removes its handler
+    pub fn unregister(interrupt: Interrupt, hart_id: usize) {
+        PLIC::mask(Context::machine(hart_id), interrupt);
+        HANDLERS[interrupt.into_bits() as usize].store(0, Ordering::Release);
+    }
+
+    fn handler(interrupt: Interrupt) -> Option<fn()> {
+        match HANDLERS[interrupt.into_bits() as usize].load(Ordering::Acquire) {
+            0 => None,
+            // note(unsafe): only ever stored from a `fn()` in `register`
+            address => Some(unsafe { core::mem::transmute::<usize, fn()>(address) }),
+        }
+    }
+
+    /// Claims, handles and completes every pending interrupt of the current hart
+    ///
+    /// Interrupts without a handler are masked on the hart, so they don't
+    /// fire again.
+    pub fn machine_external() {
+        let context = Context::machine(hart_id());
+        loop {
+            let claimed = match PLIC::claim(context) {
+                Ok(Some(claimed)) => claimed,
+                Ok(None) => break,
+                // already completed by claim
+                Err(_unknown) => continue,
+            };
+            handle(context, claimed.interrupt());
+        }
+    }
+
+    /// Like [`machine_external`], but lets higher priority interrupts preempt
+    /// the handlers
+    ///
+    /// While a handler runs, the threshold of the hart is raised to the
+    /// priority of its interrupt and machine interrupts are enabled again.
+    /// The threshold is restored before the claim is completed.
+    pub fn machine_external_nested() {
+        let context = Context::machine(hart_id());
+        loop {
+            let claimed = match PLIC::claim(context) {
+                Ok(Some(claimed)) => claimed,
+                Ok(None) => break,
+                Err(_unknown) => continue,
+            };
+            let interrupt = claimed.interrupt();
+            let threshold = PLIC::get_threshold(context);
+            let priority = PLIC::get_priority(interrupt);
+            // a nested trap overwrites mepc and mstatus.MPP; MPIE needs no
+            // saving, as it is always set while MPP is machine mode here
+            let epc = mepc::read();
+            let mpp = mstatus::read().mpp();
+            unsafe {
+                PLIC::set_threshold(context, threshold.max(priority));
+                interrupt::enable();
+            }
+            handle(context, interrupt);
+            unsafe {
+                interrupt::disable();
+                PLIC::set_threshold(context, threshold);
+                mstatus::set_mpp(mpp);
+            }
+            mepc::write(epc);
+            claimed.complete();
+        }
+    }
+
+    /// Run the handler of a claimed interrupt, or mask it if there is none
+    fn handle(context: Context, interrupt: Interrupt) {
+        match handler(interrupt) {
+            Some(handler) => handler(),
+            None => PLIC::mask(context, interrupt),
         }
     }
 }
@@ -5,32 +5,47 @@
 //! * [`UART1`](crate::pac::UART1)
 //! * [`UART2`](crate::pac::UART2)
 //! * [`UART3`](crate::pac::UART3)
+//!
+//! Every transmitter implements [`core::fmt::Write`], so `write!` works on it
+//! directly. [`print_panic`] takes over UARTHS to report a panic, and with the
+//! `log` feature enabled `init_logger` installs UARTHS as the `log` backend.
 
 use core::convert::Infallible;
+use core::fmt::{self, Write as _};
 use core::mem;
 
-use crate::clock::Clocks;
+use crate::clock::{Clocks, Retime};
 use crate::pac::{uart1, UART1, UART2, UART3, UARTHS};
+use crate::sysctl::{self, PeripheralGate};
 use crate::time::Bps;
 
 /// Extension trait that constrains UART peripherals
 pub trait SerialExt: Sized {
+    /// Clock gate and reset token of the UART, `()` for UARTHS which has none
+    type Gate;
+
     /// Configures a UART peripheral to provide serial communication
-    fn configure(self, baud_rate: Bps, clocks: &Clocks) -> Serial<Self>;
+    fn configure(self, baud_rate: Bps, clocks: &Clocks, gate: Self::Gate) -> Serial<Self>;
 }
 
 /// Serial abstraction
-pub struct Serial<UART> {
+pub struct Serial<UART: SerialExt> {
     uart: UART,
+    gate: UART::Gate,
+    baud_rate: Bps,
 }
 
-impl<UART> Serial<UART> {
+impl<UART: SerialExt> Serial<UART> {
     /// Splits the `Serial` abstraction into a transmitter and a
     /// receiver half
     #[inline]
     pub fn split(self) -> (Tx<UART>, Rx<UART>) {
         (
-            Tx { uart: self.uart },
+            Tx {
+                uart: self.uart,
+                gate: self.gate,
+                baud_rate: self.baud_rate,
+            },
             Rx {
                 // clippy allow: inner marker variable only indicates ownership, does not include actual data
                 uart: unsafe {
@@ -46,20 +61,19 @@ impl<UART> Serial<UART> {
     #[inline]
     pub fn join(tx: Tx<UART>, rx: Rx<UART>) -> Self {
         let _ = rx; // note(discard): Zero-sized typestate struct
-        Serial { uart: tx.uart }
-    }
-
-    /// Releases the UART peripheral
-    #[inline]
-    pub fn free(self) -> UART {
-        // todo: power down this UART
-        self.uart
+        Serial {
+            uart: tx.uart,
+            gate: tx.gate,
+            baud_rate: tx.baud_rate,
+        }
     }
 }
 
 /// Serial transmitter
-pub struct Tx<UART> {
+pub struct Tx<UART: SerialExt> {
     uart: UART,
+    gate: UART::Gate,
+    baud_rate: Bps,
 }
 
 /// Serial receiver
@@ -67,24 +81,139 @@ pub struct Rx<UART> {
     uart: UART,
 }
 
+impl<UART: SerialExt> fmt::Write for Tx<UART>
+where
+    Tx<UART>: embedded_io::Write,
+{
+    #[inline]
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // one byte at a time, so every byte waits for room in the transmit FIFO
+        for byte in s.as_bytes() {
+            embedded_io::Write::write(self, core::slice::from_ref(byte)).map_err(|_| fmt::Error)?;
+        }
+        Ok(())
+    }
+}
+
 impl SerialExt for UARTHS {
+    type Gate = ();
+
     #[inline]
-    fn configure(self, baud_rate: Bps, clocks: &Clocks) -> Serial<UARTHS> {
+    fn configure(self, baud_rate: Bps, clocks: &Clocks, _gate: ()) -> Serial<UARTHS> {
         let uart = self;
 
-        let div = clocks.cpu().0 / baud_rate.0 - 1;
-        unsafe {
-            uart.div.write(|w| w.bits(div));
-        }
+        set_uarths_divisor(&uart, baud_rate, clocks);
 
         uart.txctrl.write(|w| w.txen().bit(true));
         uart.rxctrl.write(|w| w.rxen().bit(true));
 
-        Serial { uart }
+        Serial {
+            uart,
+            gate: (),
+            baud_rate,
+        }
+    }
+}
+
+fn set_uarths_divisor(uart: &UARTHS, baud_rate: Bps, clocks: &Clocks) {
+    let div = clocks.cpu().0 / baud_rate.0 - 1;
+    unsafe {
+        uart.div.write(|w| w.bits(div));
+    }
+}
+
+impl Retime for Serial<UARTHS> {
+    fn retime(&mut self, clocks: &Clocks) {
+        set_uarths_divisor(&self.uart, self.baud_rate, clocks);
+    }
+}
+
+impl Retime for Tx<UARTHS> {
+    fn retime(&mut self, clocks: &Clocks) {
+        set_uarths_divisor(&self.uart, self.baud_rate, clocks);
+    }
+}
+
+impl Tx<UARTHS> {
+    /// Takes over the UARTHS transmitter regardless of its current owner
+    ///
+    /// The transmitter is re-enabled with the baud rate it was last configured with.
+    ///
+    /// # Safety
+    ///
+    /// Output may interleave with other users of UARTHS
+    unsafe fn steal() -> Self {
+        let uart = crate::pac::Peripherals::steal().UARTHS;
+        uart.txctrl.modify(|_, w| w.txen().bit(true));
+        Tx {
+            uart,
+            gate: (),
+            // only read by `retime`, which is never called on a stolen
+            // transmitter; the divisor register is left as it is
+            baud_rate: Bps(0),
+        }
+    }
+}
+
+/// Prints a panic message on UARTHS
+///
+/// This function is intended to be called from a `#[panic_handler]`; it takes over
+/// UARTHS even if its transmitter is owned elsewhere, so UARTHS must have been
+/// configured before the panic for the output to be readable.
+pub fn print_panic(info: &core::panic::PanicInfo) {
+    // note(unsafe): nothing else is going to run on this hart after a panic
+    let mut tx = unsafe { Tx::<UARTHS>::steal() };
+    let _ = write!(tx, "\r\n{}\r\n", info);
+    let _ = embedded_io::Write::flush(&mut tx);
+}
+
+/// `log` backend writing to a stolen UARTHS transmitter
+///
+/// Not hart-safe: records logged from both harts at once may interleave
+/// within a line.
+#[cfg(feature = "log")]
+struct UarthsLogger;
+
+#[cfg(feature = "log")]
+impl log::Log for UarthsLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            // note(unsafe): the transmitter has been handed over in `init_logger`
+            let mut tx = unsafe { Tx::<UARTHS>::steal() };
+            let _ = write!(tx, "[{}] {}\r\n", record.level(), record.args());
+        }
     }
+
+    fn flush(&self) {}
+}
+
+/// Installs the UARTHS transmitter as the `log` backend
+///
+/// Records up to `level` are written to UARTHS. The transmitter is consumed, as
+/// the logger owns it from now on. Records are not locked against each
+/// other, so logging from both harts at once may interleave their output.
+#[cfg(feature = "log")]
+pub fn init_logger(tx: Tx<UARTHS>, level: log::LevelFilter) -> Result<(), log::SetLoggerError> {
+    let _ = tx; // note(discard): ownership is handed over to the logger
+    log::set_logger(&UarthsLogger)?;
+    log::set_max_level(level);
+    Ok(())
 }
 
 impl Serial<UARTHS> {
+    /// Releases the UART peripheral
+    ///
+    /// UART1-3 hold a clock gate, use `release` to get it back as well.
+    #[inline]
+    pub fn free(self) -> UARTHS {
+        // todo: power down this UART
+        self.uart
+    }
+
     /// Starts listening for an interrupt event
     #[inline]
     pub fn listen(self) -> Self {
@@ -155,44 +284,42 @@ mod closed_trait {
     /// Trait to be able to generalize over UART1/UART2/UART3
     pub trait UartX: Deref<Target = super::uart1::RegisterBlock> {
         const INDEX: u8;
+        type Gate: super::PeripheralGate;
     }
 }
 use closed_trait::UartX;
 
 impl UartX for UART1 {
     const INDEX: u8 = 1;
+    type Gate = sysctl::UART1;
 }
 impl UartX for UART2 {
     const INDEX: u8 = 2;
+    type Gate = sysctl::UART2;
 }
 impl UartX for UART3 {
     const INDEX: u8 = 3;
+    type Gate = sysctl::UART3;
 }
 
 const UART_RECEIVE_FIFO_1: u32 = 0;
 const UART_SEND_FIFO_8: u32 = 3;
 
 impl<UART: UartX> SerialExt for UART {
+    type Gate = UART::Gate;
+
     #[inline]
-    fn configure(self, baud_rate: Bps, clocks: &Clocks) -> Serial<UART> {
+    fn configure(self, baud_rate: Bps, clocks: &Clocks, mut gate: UART::Gate) -> Serial<UART> {
         let uart = self;
+        gate.enable();
+        gate.reset();
 
         // Hardcode these for now:
         let data_width = 8; // 8 data bits
         let stopbit_val = 0; // 1 stop bit
         let parity_val = 0; // No parity
-                            // Note: need to make sure that UARTx clock is enabled through sysctl before here
-        let divisor = clocks.apb0().0 / baud_rate.0;
-        let dlh = ((divisor >> 12) & 0xff) as u8;
-        let dll = ((divisor >> 4) & 0xff) as u8;
-        let dlf = (divisor & 0xf) as u8;
+        set_uart_divisor(&uart, baud_rate, clocks);
         unsafe {
-            // Set Divisor Latch Access Bit (enables DLL DLH) to set baudrate
-            uart.lcr.write(|w| w.bits(1 << 7));
-            uart.dlh_ier.write(|w| w.bits(dlh.into()));
-            uart.rbr_dll_thr.write(|w| w.bits(dll.into()));
-            uart.dlf.write(|w| w.bits(dlf.into()));
-            // Clear Divisor Latch Access Bit after setting baudrate
             uart.lcr
                 .write(|w| w.bits((data_width - 5) | (stopbit_val << 2) | (parity_val << 3)));
             // Write IER
@@ -203,11 +330,51 @@ impl<UART: UartX> SerialExt for UART {
             });
         }
 
-        Serial { uart }
+        Serial {
+            uart,
+            gate,
+            baud_rate,
+        }
+    }
+}
+
+fn set_uart_divisor<UART: UartX>(uart: &UART, baud_rate: Bps, clocks: &Clocks) {
+    let divisor = clocks.apb0().0 / baud_rate.0;
+    let dlh = ((divisor >> 12) & 0xff) as u8;
+    let dll = ((divisor >> 4) & 0xff) as u8;
+    let dlf = (divisor & 0xf) as u8;
+    let lcr = uart.lcr.read().bits() & !(1 << 7);
+    unsafe {
+        // Set Divisor Latch Access Bit (enables DLL DLH) to set baudrate
+        uart.lcr.write(|w| w.bits(lcr | 1 << 7));
+        uart.dlh_ier.write(|w| w.bits(dlh.into()));
+        uart.rbr_dll_thr.write(|w| w.bits(dll.into()));
+        uart.dlf.write(|w| w.bits(dlf.into()));
+        // Clear Divisor Latch Access Bit after setting baudrate
+        uart.lcr.write(|w| w.bits(lcr));
+    }
+}
+
+impl<UART: UartX> Retime for Serial<UART> {
+    fn retime(&mut self, clocks: &Clocks) {
+        set_uart_divisor(&self.uart, self.baud_rate, clocks);
+    }
+}
+
+impl<UART: UartX> Retime for Tx<UART> {
+    fn retime(&mut self, clocks: &Clocks) {
+        set_uart_divisor(&self.uart, self.baud_rate, clocks);
     }
 }
 
 impl<UART: UartX> Serial<UART> {
+    /// Releases the UART peripheral and its clock gate, with the clock disabled
+    #[inline]
+    pub fn release(mut self) -> (UART, UART::Gate) {
+        self.gate.disable();
+        (self.uart, self.gate)
+    }
+
     /// Starts listening for an interrupt event
     #[inline]
     pub fn listen(self) -> Self {
@@ -6,29 +6,30 @@ use crate::sysctl::{self, APB0};
 /// SHA256 module abstraction
 pub struct Sha256 {
     sha256: SHA256,
+    gate: sysctl::SHA256,
 }
 
 impl Sha256 {
-    pub fn new(sha256: SHA256, apb0: &mut APB0) -> Sha256 {
+    pub fn new(sha256: SHA256, apb0: &mut APB0, mut gate: sysctl::SHA256) -> Sha256 {
         apb0.enable();
-        sysctl::clk_en_peri().modify(|_r, w| w.sha_clk_en().set_bit());
-        sysctl::peri_reset().modify(|_r, w| w.sha_reset().set_bit());
-        sysctl::peri_reset().modify(|_r, w| w.sha_reset().clear_bit());
-        Sha256 { sha256 }
+        gate.enable();
+        gate.reset();
+        Sha256 { sha256, gate }
     }
 
     pub fn new_digest(self) -> Digest {
         todo!()
     }
 
-    pub fn release(self) -> SHA256 {
-        sysctl::clk_en_peri().modify(|_r, w| w.sha_clk_en().clear_bit());
-        self.sha256
+    pub fn release(mut self) -> (SHA256, sysctl::SHA256) {
+        self.gate.disable();
+        (self.sha256, self.gate)
     }
 }
 
 pub struct Digest {
     sha256: SHA256,
+    gate: sysctl::SHA256,
 }
 
 impl Digest {
@@ -45,6 +46,7 @@ impl Digest {
     pub fn free(self) -> Sha256 {
         Sha256 {
             sha256: self.sha256,
+            gate: self.gate,
         }
     }
 }
@@ -1,13 +1,31 @@
 //! (TODO) Serial Peripheral Interface (SPI)
 
-use crate::clock::Clocks;
+use crate::clock::{Clocks, Retime};
 use crate::pac::SPI0;
-use crate::sysctl::{self, APB0};
+use crate::sysctl::{self, PeripheralGate, APB2};
+use crate::time::Hertz;
 pub use embedded_hal::spi::{Mode, Phase, Polarity};
 
-///
-pub struct Spi<SPI> {
+/// Serial Peripheral Interface
+pub struct Spi<SPI: SpiX> {
     spi: SPI,
+    gate: SPI::Gate,
+    /// source clock frequency, from the SPI threshold in sysctl
+    source: Hertz,
+    /// serial clock frequency last asked for with `set_frequency`
+    expected: Option<Hertz>,
+}
+
+mod closed_trait {
+    /// Trait to be able to generalize over SPI peripherals
+    pub trait SpiX {
+        type Gate: super::PeripheralGate;
+    }
+}
+use closed_trait::SpiX;
+
+impl SpiX for SPI0 {
+    type Gate = sysctl::SPI0;
 }
 
 impl Spi<SPI0> {
@@ -18,14 +36,14 @@ impl Spi<SPI0> {
         frame_format: FrameFormat,
         endian: Endian,
         clock: &Clocks,
-        apb0: &mut APB0,
+        apb2: &mut APB2,
+        mut gate: sysctl::SPI0,
     ) -> Self {
         let work_mode = hal_mode_to_pac(mode);
         let frame_format = frame_format_to_pac(frame_format);
         let tmod = crate::pac::spi0::ctrlr0::TMOD_A::TRANS_RECV; // todo other modes
         let endian = endian as u32;
         let data_bit_length = 8; // todo more length
-        let _ = clock; // todo
         unsafe {
             // no interrupts for now
             spi.imr.write(|w| w.bits(0x00));
@@ -50,18 +68,58 @@ impl Spi<SPI0> {
             spi.spi_ctrlr0.reset(); // standard
             spi.endian.write(|w| w.bits(endian));
         }
-        // enable APB0 bus
-        apb0.enable();
+        // enable APB2 bus
+        apb2.enable();
         // enable peripheral via sysctl
-        sysctl::clk_en_peri().modify(|_r, w| w.spi0_clk_en().set_bit());
-        Spi { spi }
+        gate.enable();
+        Spi {
+            spi,
+            gate,
+            source: clock.spi0(),
+            expected: None,
+        }
+    }
+
+    /// Returns the source clock frequency the serial clock is divided from
+    pub fn source_frequency(&self) -> Hertz {
+        self.source
+    }
+
+    /// Sets the serial clock frequency, returning the frequency actually reached
+    ///
+    /// The divider is rounded up, so the serial clock never runs faster than
+    /// expected. It is even and within 2..=65534, so the result may be away
+    /// from the expected frequency when that is out of range.
+    pub fn set_frequency(&mut self, expected_freq: impl Into<Hertz>) -> Hertz {
+        let expected_freq = expected_freq.into();
+        self.expected = Some(expected_freq);
+        let expected = expected_freq.0.max(1);
+        let baudr = self.source.0.div_ceil(expected).next_multiple_of(2);
+        let baudr = baudr.clamp(2, 65534);
+        let ssienr = self.spi.ssienr.read().bits();
+        unsafe {
+            // baudr can only be written while the controller is disabled
+            self.spi.ssienr.write(|w| w.bits(0x00));
+            self.spi.baudr.write(|w| w.bits(baudr));
+            self.spi.ssienr.write(|w| w.bits(ssienr));
+        }
+        Hertz(self.source.0 / baudr)
     }
 
     #[inline]
-    pub fn release(self) -> SPI0 {
+    pub fn release(mut self) -> (SPI0, sysctl::SPI0) {
         // power off
-        sysctl::clk_en_peri().modify(|_r, w| w.spi0_clk_en().clear_bit());
-        self.spi
+        self.gate.disable();
+        (self.spi, self.gate)
+    }
+}
+
+impl Retime for Spi<SPI0> {
+    fn retime(&mut self, clocks: &Clocks) {
+        self.source = clocks.spi0();
+        if let Some(expected) = self.expected {
+            self.set_frequency(expected);
+        }
     }
 }
 
@@ -1,11 +1,11 @@
 //! (TODO) System Controller (SYSCTL)
 
-use crate::clock::Clocks;
+use crate::clock::{ClockConfig, Clocks, Retime};
 use crate::pac::{sysctl, SYSCTL};
 use crate::time::Hertz;
 use core::sync::atomic::Ordering;
 
-const CLOCK_FREQ_IN0: u32 = 26_000_000;
+pub(crate) const CLOCK_FREQ_IN0: u32 = 26_000_000;
 
 pub(crate) fn sysctl<'a>() -> &'a sysctl::RegisterBlock {
     unsafe { &*(SYSCTL::ptr()) }
@@ -23,121 +23,488 @@ pub(crate) fn peri_reset<'a>() -> &'a sysctl::PERI_RESET {
     &sysctl().peri_reset
 }
 
-/// Accept freq_in as the input frequency,
-/// and try to find a set of parameters (nr, od, nf),
-/// which results in a frequency as near as possible to freq
-/// note for a PLL:
-///   freq_out = freq_in / nr * nf / od
-/// The reason why we don't port the complex config algorithm from the
-/// official C language SDK is that doing floating number arithmetics
-/// efficiently in no_std rust now is currently not very convenient
-fn calculate_pll_config(freq_in: u32, freq: u32) -> (u8, u8, u8) {
-    // finding a set of (nr, od, nf) which minify abs(freq * nr * od - freq_in * nf)
-    // nr, od is in [0b1,    0b10_000], nr * od is in [0b1,   0b100_000_000]
-    // nf     is in [0b1, 0b1_000_000]
-
-    // for archiving a higher accuracy, we want the nr * od as large as possible
-    // use binary search to find the largest nr_od which freq <= freq_in * 0b1_000_000 / nr_od
-    let mut left = 1;
-    let mut right = 0b1_0000 * 0b1_0000 + 1;
-    while left + 1 < right {
-        let mid = (left + right) / 2;
-        let max_freq = freq_in * 0b100_0000 / mid;
-        if freq >= max_freq {
-            // in [left, mid)
-            right = mid;
-        } else {
-            // in [mid, right)
-            left = mid;
+/// Output frequency of a PLL, freq_out = freq_in / nr * nf / od
+pub(crate) fn pll_frequency(freq_in: u32, nr: u8, nf: u8, od: u8) -> u32 {
+    (freq_in as u64 * nf as u64 / (nr as u64 * od as u64)) as u32
+}
+
+// PLL limits, ref: sysctl.c
+const PLL_VCO_MIN: u64 = 350_000_000;
+const PLL_VCO_MAX: u64 = 1_750_000_000;
+const PLL_REF_MIN: u64 = 13_671_900;
+const PLL_REF_MAX: u64 = 1_750_000_000;
+const PLL_NR_MAX: u8 = 16;
+const PLL_NF_MAX: u8 = 64;
+const PLL_OD_MAX: u8 = 16;
+
+/// Parameters of a PLL, as real values (not register values minus one)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct PllParams {
+    pub(crate) nr: u8,
+    pub(crate) nf: u8,
+    pub(crate) od: u8,
+}
+
+/// Outcome of a PLL parameter search
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct PllSolution {
+    pub(crate) params: PllParams,
+    /// Achieved output frequency, rounded down
+    pub(crate) frequency: u32,
+    /// Achieved minus requested frequency, rounded towards zero
+    pub(crate) error: i64,
+}
+
+impl PllParams {
+    /// Parameters resulting in a frequency as near as possible to freq
+    ///
+    /// Every (nr, od) pair is tried with the nearest nf on both sides, clamped
+    /// so the VCO (freq_in / nr * nf) stays within the PLL limits, and only nr
+    /// keeping the reference (freq_in / nr) within the limits is used. Ties keep the pair found first, i.e. the one
+    /// with the smallest nr and od. Returns `None` if freq_in is too low for
+    /// any valid reference frequency.
+    pub(crate) fn calculate(freq_in: u32, freq: u32) -> Option<PllSolution> {
+        let fin = freq_in as u64;
+        let fout = freq as u64;
+        // exact error is |fin * nf - fout * nr * od| / (nr * od), kept as a fraction
+        let mut best: Option<(PllParams, u64, u64)> = None;
+        for nr in 1..=PLL_NR_MAX {
+            let ref_freq = fin / nr as u64;
+            if !(PLL_REF_MIN..=PLL_REF_MAX).contains(&ref_freq) {
+                continue;
+            }
+            // nf range keeping the VCO within limits
+            let nf_min = (PLL_VCO_MIN * nr as u64).div_ceil(fin).max(1);
+            let nf_max = (PLL_VCO_MAX * nr as u64 / fin).min(PLL_NF_MAX as u64);
+            if nf_min > nf_max {
+                continue;
+            }
+            for od in 1..=PLL_OD_MAX {
+                let div = nr as u64 * od as u64;
+                let nf_low = fout * div / fin;
+                for nf in [nf_low, nf_low + 1] {
+                    let nf = nf.clamp(nf_min, nf_max);
+                    let num = (fin * nf).abs_diff(fout * div);
+                    let better = match best {
+                        Some((_, best_num, best_div)) => num * best_div < best_num * div,
+                        None => true,
+                    };
+                    if better {
+                        let params = PllParams {
+                            nr,
+                            nf: nf as u8,
+                            od,
+                        };
+                        best = Some((params, num, div));
+                    }
+                }
+            }
         }
+        best.map(|(params, _, _)| {
+            let frequency = params.frequency(freq_in);
+            PllSolution {
+                params,
+                frequency,
+                error: frequency as i64 - freq as i64,
+            }
+        })
     }
-    let nr_od = left;
-    // so we got nf
-    let nf = freq * nr_od / freq_in;
-    let nf = nf.min(0b100_0000) as u8;
 
-    // decompose nr_od
-    for nr in 1..=0b1_0000 {
-        if (nr_od / nr) * nr == nr_od {
-            return (nr as u8, (nr_od / nr) as u8, nf);
-        }
+    pub(crate) fn frequency(&self, freq_in: u32) -> u32 {
+        pll_frequency(freq_in, self.nr, self.nf, self.od)
     }
-    unreachable!()
 }
 
 pub trait SysctlExt {
     fn constrain(self) -> Parts;
 }
 
+/// Clock gate and reset token of a peripheral, for drivers generic over peripherals
+pub trait PeripheralGate {
+    /// Enable the peripheral clock
+    fn enable(&mut self);
+    /// Disable the peripheral clock
+    fn disable(&mut self);
+    /// Reset the peripheral
+    fn reset(&mut self);
+    /// Return whether the peripheral clock is enabled
+    fn is_enabled(&self) -> bool;
+    /// Create another instance of this token
+    ///
+    /// # Safety
+    ///
+    /// The peripheral must not be gated or reset behind the back of its driver
+    unsafe fn steal() -> Self;
+}
+
+macro_rules! def_peripheral_gates {
+    ($($GATE: ident: ($gate: ident, $clk_en: ident, $reset: ident, $doc: expr);)+) => {
 impl SysctlExt for SYSCTL {
     fn constrain(self) -> Parts {
         Parts {
-            aclk: ACLK { _ownership: () },
-            apb0: APB0 { _ownership: () },
-            pll0: PLL0 { _ownership: () },
+            clock: ClockControl {
+                aclk: ACLK { _ownership: () },
+                apb0: APB0 { _ownership: () },
+                apb1: APB1 { _ownership: () },
+                apb2: APB2 { _ownership: () },
+                pll0: PLL0 { _ownership: () },
+                pll1: PLL1 { _ownership: () },
+                pll2: PLL2 { _ownership: () },
+            },
+            sram0: SRAM0 { _ownership: () },
+            sram1: SRAM1 { _ownership: () },
+            power: POWER { _ownership: () },
+            $( $gate: $GATE { _ownership: () }, )+
         }
     }
 }
 
 // ref: sysctl.c
 pub struct Parts {
+    /// entry for controlling the clock tree: ACLK, PLLs and APB buses
+    pub clock: ClockControl,
+    /// entry for controlling the enable/disable/frequency of SRAM0
+    pub sram0: SRAM0,
+    /// entry for controlling the enable/disable/frequency of SRAM1
+    pub sram1: SRAM1,
+    /// entry for selecting the voltage of IO power domains
+    pub power: POWER,
+    $(
+        #[doc = concat!("entry for controlling the clock gate and reset of ", $doc)]
+        pub $gate: $GATE,
+    )+
+    // todo: OTP
+}
+
+$(
+#[doc = concat!("Clock gate and reset of ", $doc)]
+///
+/// Drivers take this token on construction, so only one of them can gate
+/// or reset the peripheral.
+pub struct $GATE {
+    _ownership: (),
+}
+
+impl $GATE {
+    /// Create another instance of this token
+    ///
+    /// # Safety
+    ///
+    /// The peripheral must not be gated or reset behind the back of its driver
+    pub unsafe fn steal() -> Self {
+        $GATE { _ownership: () }
+    }
+
+    /// Enable the peripheral clock
+    pub fn enable(&mut self) {
+        clk_en_peri().modify(|_r, w| w.$clk_en().set_bit());
+    }
+
+    /// Disable the peripheral clock
+    pub fn disable(&mut self) {
+        clk_en_peri().modify(|_r, w| w.$clk_en().clear_bit());
+    }
+
+    /// Reset the peripheral
+    pub fn reset(&mut self) {
+        peri_reset().modify(|_r, w| w.$reset().set_bit());
+        peri_reset().modify(|_r, w| w.$reset().clear_bit());
+    }
+
+    /// Return whether the peripheral clock is enabled
+    pub fn is_enabled(&self) -> bool {
+        clk_en_peri().read().$clk_en().bit()
+    }
+}
+
+impl PeripheralGate for $GATE {
+    #[inline]
+    fn enable(&mut self) {
+        $GATE::enable(self)
+    }
+
+    #[inline]
+    fn disable(&mut self) {
+        $GATE::disable(self)
+    }
+
+    #[inline]
+    fn reset(&mut self) {
+        $GATE::reset(self)
+    }
+
+    #[inline]
+    fn is_enabled(&self) -> bool {
+        $GATE::is_enabled(self)
+    }
+
+    #[inline]
+    unsafe fn steal() -> Self {
+        $GATE::steal()
+    }
+}
+)+
+    };
+}
+
+def_peripheral_gates! {
+    ROM: (rom, rom_clk_en, rom_reset, "ROM");
+    DMA: (dma, dma_clk_en, dma_reset, "DMAC");
+    AI: (ai, ai_clk_en, ai_reset, "the AI accelerator (KPU)");
+    DVP: (dvp, dvp_clk_en, dvp_reset, "DVP");
+    FFT: (fft, fft_clk_en, fft_reset, "FFT");
+    GPIO: (gpio, gpio_clk_en, gpio_reset, "GPIO");
+    SPI0: (spi0, spi0_clk_en, spi0_reset, "SPI0");
+    SPI1: (spi1, spi1_clk_en, spi1_reset, "SPI1");
+    SPI2: (spi2, spi2_clk_en, spi2_reset, "SPI2 (slave)");
+    SPI3: (spi3, spi3_clk_en, spi3_reset, "SPI3");
+    I2S0: (i2s0, i2s0_clk_en, i2s0_reset, "I2S0");
+    I2S1: (i2s1, i2s1_clk_en, i2s1_reset, "I2S1");
+    I2S2: (i2s2, i2s2_clk_en, i2s2_reset, "I2S2");
+    I2C0: (i2c0, i2c0_clk_en, i2c0_reset, "I2C0");
+    I2C1: (i2c1, i2c1_clk_en, i2c1_reset, "I2C1");
+    I2C2: (i2c2, i2c2_clk_en, i2c2_reset, "I2C2");
+    UART1: (uart1, uart1_clk_en, uart1_reset, "UART1");
+    UART2: (uart2, uart2_clk_en, uart2_reset, "UART2");
+    UART3: (uart3, uart3_clk_en, uart3_reset, "UART3");
+    AES: (aes, aes_clk_en, aes_reset, "AES");
+    FPIOA: (fpioa, fpioa_clk_en, fpioa_reset, "FPIOA");
+    TIMER0: (timer0, timer0_clk_en, timer0_reset, "TIMER0");
+    TIMER1: (timer1, timer1_clk_en, timer1_reset, "TIMER1");
+    TIMER2: (timer2, timer2_clk_en, timer2_reset, "TIMER2");
+    WDT0: (wdt0, wdt0_clk_en, wdt0_reset, "WDT0");
+    WDT1: (wdt1, wdt1_clk_en, wdt1_reset, "WDT1");
+    SHA256: (sha256, sha_clk_en, sha_reset, "SHA256");
+    RTC: (rtc, rtc_clk_en, rtc_reset, "RTC");
+}
+
+impl Parts {
+    /// Clear the reset status, so the next reset is reported on its own
+    pub fn clear_reset_reason(&mut self) {
+        sysctl()
+            .reset_status
+            .modify(|_, w| w.reset_sts_clr().set_bit());
+        sysctl()
+            .reset_status
+            .modify(|_, w| w.reset_sts_clr().clear_bit());
+    }
+}
+
+/// Clock tree controls, split from `Parts` so the clock tree can still be
+/// changed after drivers took their gate tokens out of `Parts`
+pub struct ClockControl {
     /// entry for controlling the frequency of aclk
     pub aclk: ACLK,
     /// entry for controlling the enable/disable/frequency of pll0
     pub pll0: PLL0,
+    /// entry for controlling the enable/disable/frequency of pll1
+    pub pll1: PLL1,
+    /// entry for controlling the enable/disable/frequency/input of pll2
+    pub pll2: PLL2,
     /// entry for controlling the enable/disable/frequency of apb0
     pub apb0: APB0,
-    // todo: SRAM, APB-bus, ROM, DMA, AI, PLL1, PLL2, APB1, APB2
+    /// entry for controlling the enable/disable/frequency of apb1
+    pub apb1: APB1,
+    /// entry for controlling the enable/disable/frequency of apb2
+    pub apb2: APB2,
 }
 
-impl Parts {
+impl ClockControl {
+    /// Return frequencies of the whole clock tree as it is configured now
     pub fn clocks(&self) -> Clocks {
-        Clocks {
-            aclk: self.aclk.get_frequency(),
-            apb0: self.apb0.get_frequency(),
+        ClockTree::read().clocks()
+    }
+
+    /// Change the CPU frequency, retiming the given drivers
+    ///
+    /// ACLK runs from IN0 while PLL0 is reprogrammed. Peripheral dividers are
+    /// kept, so clocks derived from PLL0 scale along; see `ClockConfig::apply`
+    /// to set them as well.
+    pub fn set_cpu_frequency(
+        &mut self,
+        frequency: impl Into<Hertz>,
+        drivers: &mut [&mut dyn Retime],
+    ) -> Clocks {
+        ClockConfig::new().cpu(frequency).apply(self, drivers)
+    }
+
+    /// Program the clock tree from old to new settings
+    ///
+    /// ACLK runs from IN0 during the change, and reprogrammed PLLs are gated
+    /// until all dividers behind them are set, so no bus or peripheral sees a
+    /// frequency above both its old and its new one.
+    pub(crate) fn apply_clock_tree(&mut self, old: &ClockTree, new: &ClockTree) {
+        self.aclk.use_external();
+        let pll0_changed = old.pll[0] != new.pll[0];
+        let pll1_changed = old.pll[1] != new.pll[1];
+        let pll2_changed = old.pll[2] != new.pll[2]
+            || old.pll2_input != new.pll2_input
+            || (pll0_changed && new.pll2_input == Pll2Input::Pll0)
+            || (pll1_changed && new.pll2_input == Pll2Input::Pll1);
+        if pll0_changed {
+            self.pll0.disable();
+        }
+        if pll1_changed {
+            self.pll1.disable();
+        }
+        if pll2_changed {
+            self.pll2.disable();
+        }
+        new.write_dividers();
+        if pll0_changed {
+            self.pll0.configure(new.pll[0]);
+            self.pll0.enable();
+        }
+        if pll1_changed {
+            self.pll1.configure(new.pll[1]);
+            self.pll1.enable();
+        }
+        // PLL2 goes last, as it may be fed from PLL0 or PLL1
+        if pll2_changed {
+            unsafe {
+                sysctl()
+                    .pll2
+                    .modify(|_, w| w.ckin_sel().bits(new.pll2_input as u8));
+            }
+            self.pll2.configure(new.pll[2]);
+            self.pll2.enable();
+        }
+        if new.aclk_pll0 {
+            self.aclk.use_pll0(new.aclk_divider_sel);
+        } else {
+            unsafe {
+                sysctl()
+                    .clk_sel0
+                    .modify(|_, w| w.aclk_divider_sel().bits(new.aclk_divider_sel));
+            }
         }
     }
 }
 
-pub struct APB0 {
+macro_rules! def_apb {
+    ($($APBX: ident: ($apbx_clk_en: ident, $apbx_clk_sel: ident, $doc: expr);)+) => {
+$(
+#[doc = $doc]
+pub struct $APBX {
     _ownership: (),
 }
 
-impl APB0 {
-    pub(crate) fn enable(&mut self) {
-        clk_en_cent().modify(|_r, w| w.apb0_clk_en().set_bit());
+impl $APBX {
+    /// Enable the bus clock
+    pub fn enable(&mut self) {
+        clk_en_cent().modify(|_r, w| w.$apbx_clk_en().set_bit());
     }
 
     pub fn set_frequency(&mut self, expected_freq: impl Into<Hertz>) -> Hertz {
         let aclk = ACLK::steal();
         let aclk_frequency = aclk.get_frequency().0 as i64;
-        // apb0_frequency = aclk_frequency / (apb0_clk_sel + 1)
-        let apb0_clk_sel = (aclk_frequency / expected_freq.into().0 as i64 - 1)
-            .max(0)
-            .min(0b111) as u8;
+        // apbx_frequency = aclk_frequency / (apbx_clk_sel + 1)
+        let apbx_clk_sel =
+            (aclk_frequency / expected_freq.into().0 as i64 - 1).clamp(0, 0b111) as u8;
         unsafe {
             sysctl()
                 .clk_sel0
-                .modify(|_, w| w.apb0_clk_sel().bits(apb0_clk_sel));
+                .modify(|_, w| w.$apbx_clk_sel().bits(apbx_clk_sel));
         }
-        Hertz(aclk_frequency as u32 / (apb0_clk_sel as u32 + 1))
+        Hertz(aclk_frequency as u32 / (apbx_clk_sel as u32 + 1))
     }
 
     pub fn get_frequency(&self) -> Hertz {
         let aclk = ACLK::steal();
         let aclk_frequency = aclk.get_frequency().0 as i64;
-        let apb0_clk_sel = sysctl().clk_sel0.read().apb0_clk_sel().bits();
-        Hertz(aclk_frequency as u32 / (apb0_clk_sel as u32 + 1))
+        let apbx_clk_sel = sysctl().clk_sel0.read().$apbx_clk_sel().bits();
+        Hertz(aclk_frequency as u32 / (apbx_clk_sel as u32 + 1))
     }
 }
+)+
+    };
+}
 
-// pub struct APB1 {
-//     _ownership: ()
-// }
+def_apb! {
+    APB0: (apb0_clk_en, apb0_clk_sel, "APB0 bus, serving GPIO, UART1-3, SPI2 (slave), I2S, I2C, FPIOA, SHA256 and timers");
+    APB1: (apb1_clk_en, apb1_clk_sel, "APB1 bus, serving AES, WDT, OTP and RTC");
+    APB2: (apb2_clk_en, apb2_clk_sel, "APB2 bus, serving SPI0 and SPI1");
+}
 
-// pub struct APB2 {
-//     _ownership: ()
-// }
+macro_rules! def_pll {
+    ($PLLX: ident, $pllx: ident, $pll_lockx: ident, $pll_slip_clearx: ident, $lock_mask: expr) => {
+        impl $PLLX {
+            #[inline(always)]
+            fn is_locked(&self) -> bool {
+                sysctl().pll_lock.read().$pll_lockx().bits() & $lock_mask == $lock_mask
+            }
+
+            fn lock(&mut self) {
+                while !self.is_locked() {
+                    sysctl()
+                        .pll_lock
+                        .modify(|_, w| w.$pll_slip_clearx().set_bit())
+                }
+            }
+
+            #[inline(always)]
+            fn reset(&mut self) {
+                sysctl().$pllx.modify(|_, w| w.reset().clear_bit());
+                sysctl().$pllx.modify(|_, w| w.reset().set_bit());
+                core::sync::atomic::compiler_fence(Ordering::SeqCst);
+                core::sync::atomic::compiler_fence(Ordering::SeqCst);
+                sysctl().$pllx.modify(|_, w| w.reset().clear_bit());
+            }
+
+            #[doc = concat!("enable ", stringify!($PLLX))]
+            pub fn enable(&mut self) {
+                sysctl()
+                    .$pllx
+                    .modify(|_, w| w.bypass().clear_bit().pwrd().set_bit());
+                self.reset();
+                self.lock();
+                sysctl().$pllx.modify(|_, w| w.out_en().set_bit());
+            }
+
+            /// Write PLL parameters, the PLL should be disabled when calling this
+            pub(crate) fn configure(&mut self, params: PllParams) {
+                unsafe {
+                    sysctl().$pllx.modify(|_, w| {
+                        w.clkr()
+                            .bits(params.nr - 1)
+                            .clkf()
+                            .bits(params.nf - 1)
+                            .clkod()
+                            .bits(params.od - 1)
+                            .bwadj()
+                            .bits(params.nf - 1)
+                    });
+                }
+            }
+
+            /// Read PLL parameters
+            pub(crate) fn params(&self) -> PllParams {
+                let r = sysctl().$pllx.read();
+                PllParams {
+                    nr: r.clkr().bits() + 1,
+                    nf: r.clkf().bits() + 1,
+                    od: r.clkod().bits() + 1,
+                }
+            }
+
+            /// Disable PLL, reprogram it to frequency and enable it again
+            fn reprogram(&mut self, frequency: u32) -> Hertz {
+                self.disable();
+                let freq_in = self.input_frequency().0;
+                if let Some(solution) = PllParams::calculate(freq_in, frequency) {
+                    self.configure(solution.params);
+                }
+                self.enable();
+                self.get_frequency()
+            }
+
+            #[doc = concat!("Return the frequency of ", stringify!($PLLX))]
+            pub fn get_frequency(&self) -> Hertz {
+                Hertz(self.params().frequency(self.input_frequency().0))
+            }
+        }
+    };
+}
 
 /// PLL0, which source is CLOCK_FREQ_IN0,
 /// and the output can be used on ACLK(CPU), SPIs, etc.
@@ -145,41 +512,15 @@ pub struct PLL0 {
     _ownership: (),
 }
 
+def_pll!(PLL0, pll0, pll_lock0, pll_slip_clear0, 0b11);
+
 impl PLL0 {
     pub(crate) fn steal() -> Self {
         PLL0 { _ownership: () }
     }
 
-    #[inline(always)]
-    fn is_locked(&self) -> bool {
-        sysctl().pll_lock.read().pll_lock0() == 0b11
-    }
-
-    fn lock(&mut self) {
-        while !self.is_locked() {
-            sysctl()
-                .pll_lock
-                .modify(|_, w| w.pll_slip_clear0().set_bit())
-        }
-    }
-
-    #[inline(always)]
-    fn reset(&mut self) {
-        sysctl().pll0.modify(|_, w| w.reset().clear_bit());
-        sysctl().pll0.modify(|_, w| w.reset().set_bit());
-        core::sync::atomic::compiler_fence(Ordering::SeqCst);
-        core::sync::atomic::compiler_fence(Ordering::SeqCst);
-        sysctl().pll0.modify(|_, w| w.reset().clear_bit());
-    }
-
-    /// enable PLL0
-    pub fn enable(&mut self) {
-        sysctl()
-            .pll0
-            .modify(|_, w| w.bypass().clear_bit().pwrd().set_bit());
-        self.reset();
-        self.lock();
-        sysctl().pll0.modify(|_, w| w.out_en().set_bit());
+    fn input_frequency(&self) -> Hertz {
+        Hertz(CLOCK_FREQ_IN0)
     }
 
     /// disable PLL0
@@ -194,39 +535,122 @@ impl PLL0 {
     /// Set frequency of PLL0
     /// Will set the frequency of PLL0 as close to frequency as possible
     /// Return the real frequency of the PLL0
+    ///
+    /// Running drivers are not told about the change; prefer
+    /// `ClockControl::set_cpu_frequency` to scale the CPU at runtime.
     pub fn set_frequency(&mut self, frequency: impl Into<Hertz>) -> Hertz {
         let is_aclk_using = sysctl().clk_sel0.read().aclk_sel().bit();
         if is_aclk_using {
             sysctl().clk_sel0.modify(|_, w| w.aclk_sel().clear_bit());
         }
-        self.disable();
-        let (nr, od, nf) = calculate_pll_config(CLOCK_FREQ_IN0, frequency.into().0);
-        unsafe {
-            sysctl().pll0.modify(|_, w| {
-                w.clkr()
-                    .bits(nr - 1)
-                    .clkf()
-                    .bits(nf - 1)
-                    .clkod()
-                    .bits(od - 1)
-                    .bwadj()
-                    .bits(nf - 1)
-            });
-        }
-        self.enable();
+        let ans = self.reprogram(frequency.into().0);
         // recover aclk_sel
         if is_aclk_using {
             sysctl().clk_sel0.modify(|_, w| w.aclk_sel().set_bit());
         }
-        Hertz(CLOCK_FREQ_IN0 / nr as u32 * nf as u32 / od as u32)
+        ans
     }
+}
 
-    /// Return the frequency of PLL0
-    pub fn get_frequency(&self) -> Hertz {
-        let nr = sysctl().pll0.read().clkr().bits() + 1;
-        let nf = sysctl().pll0.read().clkf().bits() + 1;
-        let od = sysctl().pll0.read().clkod().bits() + 1;
-        Hertz(CLOCK_FREQ_IN0 / nr as u32 * nf as u32 / od as u32)
+/// PLL1, which source is CLOCK_FREQ_IN0,
+/// and the output can be used on the AI accelerator (KPU)
+pub struct PLL1 {
+    _ownership: (),
+}
+
+def_pll!(PLL1, pll1, pll_lock1, pll_slip_clear1, 0b11);
+
+impl PLL1 {
+    pub(crate) fn steal() -> Self {
+        PLL1 { _ownership: () }
+    }
+
+    fn input_frequency(&self) -> Hertz {
+        Hertz(CLOCK_FREQ_IN0)
+    }
+
+    /// disable PLL1
+    pub fn disable(&mut self) {
+        sysctl()
+            .pll1
+            .modify(|_, w| w.bypass().set_bit().pwrd().clear_bit().out_en().clear_bit());
+    }
+
+    /// Set frequency of PLL1
+    /// Will set the frequency of PLL1 as close to frequency as possible
+    /// Return the real frequency of the PLL1
+    pub fn set_frequency(&mut self, frequency: impl Into<Hertz>) -> Hertz {
+        self.reprogram(frequency.into().0)
+    }
+}
+
+/// Reference input of PLL2
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pll2Input {
+    /// External oscillator, ie. CLOCK_FREQ_IN0
+    In0 = 0,
+    /// Output of PLL0
+    Pll0 = 1,
+    /// Output of PLL1
+    Pll1 = 2,
+}
+
+/// PLL2, which source is selectable from CLOCK_FREQ_IN0, PLL0 or PLL1,
+/// and the output can be used on I2S devices
+pub struct PLL2 {
+    _ownership: (),
+}
+
+def_pll!(PLL2, pll2, pll_lock2, pll_slip_clear2, 0b01);
+
+impl PLL2 {
+    pub(crate) fn steal() -> Self {
+        PLL2 { _ownership: () }
+    }
+
+    fn input_frequency(&self) -> Hertz {
+        match self.get_input() {
+            Pll2Input::In0 => Hertz(CLOCK_FREQ_IN0),
+            Pll2Input::Pll0 => PLL0::steal().get_frequency(),
+            Pll2Input::Pll1 => PLL1::steal().get_frequency(),
+        }
+    }
+
+    /// disable PLL2
+    pub fn disable(&mut self) {
+        sysctl()
+            .pll2
+            .modify(|_, w| w.bypass().set_bit().pwrd().clear_bit().out_en().clear_bit());
+    }
+
+    /// Return the reference input of PLL2
+    pub fn get_input(&self) -> Pll2Input {
+        match sysctl().pll2.read().ckin_sel().bits() {
+            0 => Pll2Input::In0,
+            1 => Pll2Input::Pll0,
+            _ => Pll2Input::Pll1,
+        }
+    }
+
+    /// Select the reference input of PLL2
+    ///
+    /// PLL2 is locked again on the new input with its current parameters, so
+    /// its output frequency scales with the input; use `set_frequency` afterwards
+    /// to get the desired frequency back.
+    pub fn set_input(&mut self, input: Pll2Input) {
+        self.disable();
+        unsafe {
+            sysctl().pll2.modify(|_, w| w.ckin_sel().bits(input as u8));
+        }
+        self.enable();
+    }
+
+    /// Set frequency of PLL2
+    /// Will set the frequency of PLL2 as close to frequency as possible,
+    /// based on the frequency of its current reference input
+    /// Return the real frequency of the PLL2
+    pub fn set_frequency(&mut self, frequency: impl Into<Hertz>) -> Hertz {
+        self.reprogram(frequency.into().0)
     }
 }
 
@@ -266,6 +690,9 @@ impl ACLK {
     /// if frequency == CLOCK_FREQ_IN0, use external clock directly
     /// else frequency settings here are based on existing settings on PLL0
     /// We won't adjust PLL0 here because there are so many devices based on it.
+    ///
+    /// Running drivers are not told about the change; prefer
+    /// `ClockControl::set_cpu_frequency` to scale the CPU at runtime.
     pub fn set_frequency(&mut self, expected_freq: impl Into<Hertz>) -> Hertz {
         let expected_freq = expected_freq.into().0;
         if expected_freq == CLOCK_FREQ_IN0 {
@@ -302,3 +729,777 @@ impl ACLK {
         }
     }
 }
+
+/// SRAM0 bank, clocked from ACLK
+pub struct SRAM0 {
+    _ownership: (),
+}
+
+impl SRAM0 {
+    /// Enable the SRAM0 clock
+    pub fn enable(&mut self) {
+        clk_en_cent().modify(|_r, w| w.sram0_clk_en().set_bit());
+    }
+}
+
+/// SRAM1 bank, clocked from ACLK
+pub struct SRAM1 {
+    _ownership: (),
+}
+
+impl SRAM1 {
+    /// Enable the SRAM1 clock
+    pub fn enable(&mut self) {
+        clk_en_cent().modify(|_r, w| w.sram1_clk_en().set_bit());
+    }
+}
+
+macro_rules! def_clock_enables {
+    (
+        cent: [$($cent: ident: $cent_en: ident,)+]
+        peri: [$($peri: ident: $peri_en: ident,)+]
+    ) => {
+/// Clock enable bits of buses (clk_en_cent) and peripherals (clk_en_peri)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClockEnables {
+    $( pub $cent: bool, )+
+    $( pub $peri: bool, )+
+}
+
+impl ClockEnables {
+    /// Read every clock enable bit
+    pub fn read() -> Self {
+        let cent = clk_en_cent().read();
+        let peri = clk_en_peri().read();
+        ClockEnables {
+            $( $cent: cent.$cent_en().bit(), )+
+            $( $peri: peri.$peri_en().bit(), )+
+        }
+    }
+
+    /// (name, enabled) of every clock enable bit
+    pub(crate) fn entries(&self) -> impl Iterator<Item = (&'static str, bool)> {
+        [
+            $( (stringify!($cent), self.$cent), )+
+            $( (stringify!($peri), self.$peri), )+
+        ]
+        .into_iter()
+    }
+}
+    };
+}
+
+def_clock_enables! {
+    cent: [
+        cpu: cpu_clk_en,
+        sram0: sram0_clk_en,
+        sram1: sram1_clk_en,
+        apb0: apb0_clk_en,
+        apb1: apb1_clk_en,
+        apb2: apb2_clk_en,
+    ]
+    peri: [
+        rom: rom_clk_en,
+        dma: dma_clk_en,
+        ai: ai_clk_en,
+        dvp: dvp_clk_en,
+        fft: fft_clk_en,
+        gpio: gpio_clk_en,
+        spi0: spi0_clk_en,
+        spi1: spi1_clk_en,
+        spi2: spi2_clk_en,
+        spi3: spi3_clk_en,
+        i2s0: i2s0_clk_en,
+        i2s1: i2s1_clk_en,
+        i2s2: i2s2_clk_en,
+        i2c0: i2c0_clk_en,
+        i2c1: i2c1_clk_en,
+        i2c2: i2c2_clk_en,
+        uart1: uart1_clk_en,
+        uart2: uart2_clk_en,
+        uart3: uart3_clk_en,
+        aes: aes_clk_en,
+        fpioa: fpioa_clk_en,
+        timer0: timer0_clk_en,
+        timer1: timer1_clk_en,
+        timer2: timer2_clk_en,
+        wdt0: wdt0_clk_en,
+        wdt1: wdt1_clk_en,
+        sha256: sha_clk_en,
+        otp: otp_clk_en,
+        rtc: rtc_clk_en,
+    ]
+}
+
+/// Whether the output of PLL0, PLL1 and PLL2 is enabled
+pub(crate) fn pll_enabled() -> [bool; 3] {
+    [
+        sysctl().pll0.read().out_en().bit(),
+        sysctl().pll1.read().out_en().bit(),
+        sysctl().pll2.read().out_en().bit(),
+    ]
+}
+
+/// Cause of the last reset
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResetReason {
+    /// Power on, no reset status is set
+    PowerOn,
+    /// External reset pin
+    Pin,
+    /// Watchdog 0 timeout
+    Wdt0,
+    /// Watchdog 1 timeout
+    Wdt1,
+    /// Software reset, see [`soft_reset`]
+    Soft,
+}
+
+/// Return the cause of the last reset
+///
+/// Status bits stick until cleared with `Parts::clear_reset_reason`; when
+/// several are set the same precedence as the Kendryte SDK is used.
+pub fn reset_reason() -> ResetReason {
+    let r = sysctl().reset_status.read();
+    if r.wdt0_reset_sts().bit() {
+        ResetReason::Wdt0
+    } else if r.wdt1_reset_sts().bit() {
+        ResetReason::Wdt1
+    } else if r.soft_reset_sts().bit() {
+        ResetReason::Soft
+    } else if r.pin_reset_sts().bit() {
+        ResetReason::Pin
+    } else {
+        ResetReason::PowerOn
+    }
+}
+
+/// Reset the whole chip
+pub fn soft_reset() -> ! {
+    sysctl().soft_reset.write(|w| w.soft_reset().set_bit());
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+macro_rules! def_dma_select {
+    ($($Select: ident: ($value: literal, $doc: expr);)+) => {
+/// DMA handshake request source, for binding a DMAC channel to a peripheral
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum DmaSelect {
+    $(
+        #[doc = concat!($doc, " request")]
+        $Select = $value,
+    )+
+}
+
+impl DmaSelect {
+    fn from_bits(bits: u8) -> Option<DmaSelect> {
+        match bits {
+            $( $value => Some(DmaSelect::$Select), )+
+            _ => None,
+        }
+    }
+}
+    };
+}
+
+def_dma_select! {
+    Ssi0Rx: (0, "SSI0 receive");
+    Ssi0Tx: (1, "SSI0 transmit");
+    Ssi1Rx: (2, "SSI1 receive");
+    Ssi1Tx: (3, "SSI1 transmit");
+    Ssi2Rx: (4, "SSI2 receive");
+    Ssi2Tx: (5, "SSI2 transmit");
+    Ssi3Rx: (6, "SSI3 receive");
+    Ssi3Tx: (7, "SSI3 transmit");
+    I2c0Rx: (8, "I2C0 receive");
+    I2c0Tx: (9, "I2C0 transmit");
+    I2c1Rx: (10, "I2C1 receive");
+    I2c1Tx: (11, "I2C1 transmit");
+    I2c2Rx: (12, "I2C2 receive");
+    I2c2Tx: (13, "I2C2 transmit");
+    Uart1Rx: (14, "UART1 receive");
+    Uart1Tx: (15, "UART1 transmit");
+    Uart2Rx: (16, "UART2 receive");
+    Uart2Tx: (17, "UART2 transmit");
+    Uart3Rx: (18, "UART3 receive");
+    Uart3Tx: (19, "UART3 transmit");
+    Aes: (20, "AES");
+    ShaRx: (21, "SHA256 receive");
+    AiRx: (22, "AI accelerator receive");
+    FftRx: (23, "FFT receive");
+    FftTx: (24, "FFT transmit");
+    I2s0Tx: (25, "I2S0 transmit");
+    I2s0Rx: (26, "I2S0 receive");
+    I2s1Tx: (27, "I2S1 transmit");
+    I2s1Rx: (28, "I2S1 receive");
+    I2s2Tx: (29, "I2S2 transmit");
+    I2s2Rx: (30, "I2S2 receive");
+    I2s0BfDir: (31, "I2S0 beamforming direction");
+    I2s0BfVoice: (32, "I2S0 beamforming voice");
+}
+
+/// Bind a DMAC channel (0 to 5) to a request source, in dma_sel0/dma_sel1
+pub(crate) fn set_dma_select(channel: usize, select: DmaSelect) {
+    let offset = (channel % 5) * 6;
+    let mask = 0b11_1111 << offset;
+    let bits = (select as u32) << offset;
+    // note(unsafe): every request source value is valid for the 6-bit field
+    unsafe {
+        if channel < 5 {
+            sysctl()
+                .dma_sel0
+                .modify(|r, w| w.bits((r.bits() & !mask) | bits));
+        } else {
+            sysctl()
+                .dma_sel1
+                .modify(|r, w| w.bits((r.bits() & !mask) | bits));
+        }
+    }
+}
+
+/// Return the request source a DMAC channel (0 to 5) is bound to
+pub(crate) fn get_dma_select(channel: usize) -> Option<DmaSelect> {
+    let offset = (channel % 5) * 6;
+    let bits = if channel < 5 {
+        sysctl().dma_sel0.read().bits()
+    } else {
+        sysctl().dma_sel1.read().bits()
+    };
+    DmaSelect::from_bits(((bits >> offset) & 0b11_1111) as u8)
+}
+
+/// IO power domain, each one powering six consecutive IO pins
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PowerDomain {
+    /// IO0 to IO5
+    Bank0 = 0,
+    /// IO6 to IO11
+    Bank1 = 1,
+    /// IO12 to IO17
+    Bank2 = 2,
+    /// IO18 to IO23
+    Bank3 = 3,
+    /// IO24 to IO29
+    Bank4 = 4,
+    /// IO30 to IO35
+    Bank5 = 5,
+    /// IO36 to IO41
+    Bank6 = 6,
+    /// IO42 to IO47
+    Bank7 = 7,
+}
+
+impl PowerDomain {
+    /// Return the power domain of the IO pin with given index
+    ///
+    /// # Panics
+    ///
+    /// Panics if the index is not one of the 48 IO pins.
+    pub const fn of_pin(index: u8) -> PowerDomain {
+        match index / 6 {
+            0 => PowerDomain::Bank0,
+            1 => PowerDomain::Bank1,
+            2 => PowerDomain::Bank2,
+            3 => PowerDomain::Bank3,
+            4 => PowerDomain::Bank4,
+            5 => PowerDomain::Bank5,
+            6 => PowerDomain::Bank6,
+            7 => PowerDomain::Bank7,
+            _ => panic!("invalid IO pin index"),
+        }
+    }
+
+    /// Return the indices of IO pins in this power domain
+    pub fn pins(self) -> core::ops::Range<u8> {
+        let first = self as u8 * 6;
+        first..first + 6
+    }
+}
+
+/// Voltage of an IO power domain
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IoVoltage {
+    /// 3.3V, the reset value
+    V33 = 0,
+    /// 1.8V
+    V18 = 1,
+}
+
+/// IO power mode selection
+///
+/// The selected voltage must match the one actually supplied to the bank;
+/// driving a 1.8V bank in 3.3V mode can damage the chip or connected parts.
+pub struct POWER {
+    _ownership: (),
+}
+
+impl POWER {
+    /// Select the voltage of an IO power domain
+    pub fn set_io_voltage(&mut self, domain: PowerDomain, voltage: IoVoltage) {
+        let bit = 1 << domain as u32;
+        // note(unsafe): power_mode_sel0..7 are the low 8 bits, one per domain
+        sysctl().power_sel.modify(|r, w| unsafe {
+            match voltage {
+                IoVoltage::V33 => w.bits(r.bits() & !bit),
+                IoVoltage::V18 => w.bits(r.bits() | bit),
+            }
+        });
+    }
+
+    /// Return the selected voltage of an IO power domain
+    pub fn get_io_voltage(&self, domain: PowerDomain) -> IoVoltage {
+        io_voltage(domain)
+    }
+}
+
+pub(crate) fn io_voltage(domain: PowerDomain) -> IoVoltage {
+    if sysctl().power_sel.read().bits() & (1 << domain as u32) != 0 {
+        IoVoltage::V18
+    } else {
+        IoVoltage::V33
+    }
+}
+
+/// Source of a divided clock
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Source {
+    In0,
+    Pll0,
+    Pll1,
+    Pll2,
+    Aclk,
+    /// IN0 or PLL0, according to the given entry of `ClockTree::pll0_sel`
+    In0OrPll0(usize),
+}
+
+/// Kind of a clock divider
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Divider {
+    /// freq = source / (threshold + 1)
+    Gated,
+    /// freq = source / ((threshold + 1) * 2)
+    Even,
+}
+
+impl Divider {
+    fn step(self) -> u64 {
+        match self {
+            Divider::Gated => 1,
+            Divider::Even => 2,
+        }
+    }
+
+    pub(crate) fn frequency(self, source: u32, threshold: u32) -> u32 {
+        (source as u64 / ((threshold as u64 + 1) * self.step())) as u32
+    }
+
+    /// Smallest threshold in 0..=max whose frequency does not go above target
+    pub(crate) fn threshold(self, source: u32, target: u32, max: u32) -> u32 {
+        if target == 0 {
+            return max;
+        }
+        let divisor = target as u64 * self.step();
+        let div = (source as u64).div_ceil(divisor);
+        (div.max(1) - 1).min(max as u64) as u32
+    }
+}
+
+macro_rules! def_thresholds {
+    ($($Threshold: ident: ($clk_th: expr, $offset: expr, $width: expr, $source: expr, $divider: ident);)+) => {
+/// Peripheral clock dividers in the clk_th0..clk_th6 registers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Threshold {
+    $( $Threshold, )+
+}
+
+impl Threshold {
+    pub(crate) const ALL: &'static [Threshold] = &[$( Threshold::$Threshold, )+];
+
+    /// (clk_th register index, bit offset, bit width)
+    fn field(self) -> (usize, u32, u32) {
+        match self {
+            $( Threshold::$Threshold => ($clk_th, $offset, $width), )+
+        }
+    }
+
+    pub(crate) fn source(self) -> Source {
+        match self {
+            $( Threshold::$Threshold => $source, )+
+        }
+    }
+
+    pub(crate) fn divider(self) -> Divider {
+        match self {
+            $( Threshold::$Threshold => Divider::$divider, )+
+        }
+    }
+}
+    };
+}
+
+def_thresholds! {
+    Sram0: (0, 0, 4, Source::Aclk, Gated);
+    Sram1: (0, 4, 4, Source::Aclk, Gated);
+    Ai: (0, 8, 4, Source::Pll1, Gated);
+    Dvp: (0, 12, 4, Source::Aclk, Gated);
+    Rom: (0, 16, 4, Source::Aclk, Gated);
+    Spi0: (1, 0, 8, Source::Pll0, Even);
+    Spi1: (1, 8, 8, Source::Pll0, Even);
+    Spi2: (1, 16, 8, Source::Pll0, Even);
+    Spi3: (1, 24, 8, Source::In0OrPll0(0), Even);
+    Timer0: (2, 0, 8, Source::In0OrPll0(1), Even);
+    Timer1: (2, 8, 8, Source::In0OrPll0(2), Even);
+    Timer2: (2, 16, 8, Source::In0OrPll0(3), Even);
+    I2s0: (3, 0, 16, Source::Pll2, Even);
+    I2s1: (3, 16, 16, Source::Pll2, Even);
+    I2s2: (4, 0, 16, Source::Pll2, Even);
+    I2s0Mclk: (4, 16, 8, Source::Pll2, Even);
+    I2s1Mclk: (4, 24, 8, Source::Pll2, Even);
+    I2s2Mclk: (5, 0, 8, Source::Pll2, Even);
+    I2c0: (5, 8, 8, Source::Pll0, Even);
+    I2c1: (5, 16, 8, Source::Pll0, Even);
+    I2c2: (5, 24, 8, Source::Pll0, Even);
+    Wdt0: (6, 0, 8, Source::In0, Even);
+    Wdt1: (6, 8, 8, Source::In0, Even);
+}
+
+impl Threshold {
+    /// Largest threshold value the field can hold
+    pub(crate) fn max(self) -> u32 {
+        let (_, _, width) = self.field();
+        (1 << width) - 1
+    }
+}
+
+macro_rules! def_threshold_frequency {
+    ($($TYPE: ident: ($get: ident, $set: ident, $Threshold: ident, $doc: expr);)+) => {
+$(
+impl $TYPE {
+    #[doc = concat!("Return the frequency of ", $doc)]
+    pub fn $get(&self) -> Hertz {
+        Hertz(ClockTree::read().threshold_frequency(Threshold::$Threshold))
+    }
+
+    #[doc = concat!("Set the divider of ", $doc, ", returning the frequency actually reached")]
+    ///
+    /// The divider is chosen so that the frequency gets as close as possible
+    /// to the expected one without going above it. Clocks frozen before
+    /// keep their old value; use `ClockControl::clocks` to get the new ones.
+    pub fn $set(&mut self, expected_freq: impl Into<Hertz>) -> Hertz {
+        let mut tree = ClockTree::read();
+        tree.fit_threshold(Threshold::$Threshold, expected_freq.into().0);
+        tree.write_dividers();
+        Hertz(tree.threshold_frequency(Threshold::$Threshold))
+    }
+}
+)+
+    };
+}
+
+def_threshold_frequency! {
+    SRAM0: (get_frequency, set_frequency, Sram0, "SRAM0, derived from ACLK");
+    SRAM1: (get_frequency, set_frequency, Sram1, "SRAM1, derived from ACLK");
+    AI: (get_frequency, set_frequency, Ai, "the AI accelerator, derived from PLL1");
+    DVP: (get_frequency, set_frequency, Dvp, "DVP, derived from ACLK");
+    ROM: (get_frequency, set_frequency, Rom, "ROM, derived from ACLK");
+    SPI0: (get_frequency, set_frequency, Spi0, "SPI0 source clock, derived from PLL0");
+    SPI1: (get_frequency, set_frequency, Spi1, "SPI1 source clock, derived from PLL0");
+    SPI2: (get_frequency, set_frequency, Spi2, "SPI2 source clock, derived from PLL0");
+    SPI3: (get_frequency, set_frequency, Spi3, "SPI3 source clock, derived from IN0 or PLL0");
+    TIMER0: (get_frequency, set_frequency, Timer0, "TIMER0 source clock, derived from IN0 or PLL0");
+    TIMER1: (get_frequency, set_frequency, Timer1, "TIMER1 source clock, derived from IN0 or PLL0");
+    TIMER2: (get_frequency, set_frequency, Timer2, "TIMER2 source clock, derived from IN0 or PLL0");
+    I2S0: (get_frequency, set_frequency, I2s0, "I2S0 source clock, derived from PLL2");
+    I2S1: (get_frequency, set_frequency, I2s1, "I2S1 source clock, derived from PLL2");
+    I2S2: (get_frequency, set_frequency, I2s2, "I2S2 source clock, derived from PLL2");
+    I2S0: (get_mclk_frequency, set_mclk_frequency, I2s0Mclk, "I2S0 master clock, derived from PLL2");
+    I2S1: (get_mclk_frequency, set_mclk_frequency, I2s1Mclk, "I2S1 master clock, derived from PLL2");
+    I2S2: (get_mclk_frequency, set_mclk_frequency, I2s2Mclk, "I2S2 master clock, derived from PLL2");
+    I2C0: (get_frequency, set_frequency, I2c0, "I2C0 source clock, derived from PLL0");
+    I2C1: (get_frequency, set_frequency, I2c1, "I2C1 source clock, derived from PLL0");
+    I2C2: (get_frequency, set_frequency, I2c2, "I2C2 source clock, derived from PLL0");
+    WDT0: (get_frequency, set_frequency, Wdt0, "WDT0 source clock, derived from IN0");
+    WDT1: (get_frequency, set_frequency, Wdt1, "WDT1 source clock, derived from IN0");
+}
+
+/// Register level snapshot of the clock tree
+///
+/// This only holds plain values, so frequencies can be derived from (and
+/// solved into) it without touching the hardware.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct ClockTree {
+    pub(crate) pll: [PllParams; 3],
+    pub(crate) pll2_input: Pll2Input,
+    /// ACLK runs from PLL0 if set, from IN0 otherwise
+    pub(crate) aclk_pll0: bool,
+    pub(crate) aclk_divider_sel: u8,
+    pub(crate) apb_clk_sel: [u8; 3],
+    /// Whether SPI3, TIMER0, TIMER1 and TIMER2 run from PLL0 instead of IN0
+    pub(crate) pll0_sel: [bool; 4],
+    pub(crate) clk_th: [u32; 7],
+}
+
+impl ClockTree {
+    /// Read the current settings from sysctl registers
+    pub(crate) fn read() -> ClockTree {
+        let clk_sel0 = sysctl().clk_sel0.read();
+        ClockTree {
+            pll: [
+                PLL0::steal().params(),
+                PLL1::steal().params(),
+                PLL2::steal().params(),
+            ],
+            pll2_input: PLL2::steal().get_input(),
+            aclk_pll0: clk_sel0.aclk_sel().bit(),
+            aclk_divider_sel: clk_sel0.aclk_divider_sel().bits(),
+            apb_clk_sel: [
+                clk_sel0.apb0_clk_sel().bits(),
+                clk_sel0.apb1_clk_sel().bits(),
+                clk_sel0.apb2_clk_sel().bits(),
+            ],
+            pll0_sel: [
+                clk_sel0.spi3_clk_sel().bit(),
+                clk_sel0.timer0_clk_sel().bit(),
+                clk_sel0.timer1_clk_sel().bit(),
+                clk_sel0.timer2_clk_sel().bit(),
+            ],
+            clk_th: [
+                sysctl().clk_th0.read().bits(),
+                sysctl().clk_th1.read().bits(),
+                sysctl().clk_th2.read().bits(),
+                sysctl().clk_th3.read().bits(),
+                sysctl().clk_th4.read().bits(),
+                sysctl().clk_th5.read().bits(),
+                sysctl().clk_th6.read().bits(),
+            ],
+        }
+    }
+
+    /// Write bus and peripheral dividers, leaving PLLs and ACLK as they are
+    fn write_dividers(&self) {
+        unsafe {
+            sysctl().clk_sel0.modify(|_, w| {
+                w.apb0_clk_sel()
+                    .bits(self.apb_clk_sel[0])
+                    .apb1_clk_sel()
+                    .bits(self.apb_clk_sel[1])
+                    .apb2_clk_sel()
+                    .bits(self.apb_clk_sel[2])
+                    .spi3_clk_sel()
+                    .bit(self.pll0_sel[0])
+                    .timer0_clk_sel()
+                    .bit(self.pll0_sel[1])
+                    .timer1_clk_sel()
+                    .bit(self.pll0_sel[2])
+                    .timer2_clk_sel()
+                    .bit(self.pll0_sel[3])
+            });
+            sysctl().clk_th0.write(|w| w.bits(self.clk_th[0]));
+            sysctl().clk_th1.write(|w| w.bits(self.clk_th[1]));
+            sysctl().clk_th2.write(|w| w.bits(self.clk_th[2]));
+            sysctl().clk_th3.write(|w| w.bits(self.clk_th[3]));
+            sysctl().clk_th4.write(|w| w.bits(self.clk_th[4]));
+            sysctl().clk_th5.write(|w| w.bits(self.clk_th[5]));
+            sysctl().clk_th6.write(|w| w.bits(self.clk_th[6]));
+        }
+    }
+
+    pub(crate) fn pll_input(&self, index: usize) -> u32 {
+        match (index, self.pll2_input) {
+            (2, Pll2Input::Pll0) => self.pll_output(0),
+            (2, Pll2Input::Pll1) => self.pll_output(1),
+            _ => CLOCK_FREQ_IN0,
+        }
+    }
+
+    pub(crate) fn pll_output(&self, index: usize) -> u32 {
+        self.pll[index].frequency(self.pll_input(index))
+    }
+
+    pub(crate) fn aclk(&self) -> u32 {
+        if self.aclk_pll0 {
+            self.pll_output(0) / (2 << self.aclk_divider_sel)
+        } else {
+            CLOCK_FREQ_IN0
+        }
+    }
+
+    pub(crate) fn apb(&self, index: usize) -> u32 {
+        Divider::Gated.frequency(self.aclk(), self.apb_clk_sel[index] as u32)
+    }
+
+    pub(crate) fn source_frequency(&self, source: Source) -> u32 {
+        match source {
+            Source::In0 => CLOCK_FREQ_IN0,
+            Source::Pll0 => self.pll_output(0),
+            Source::Pll1 => self.pll_output(1),
+            Source::Pll2 => self.pll_output(2),
+            Source::Aclk => self.aclk(),
+            Source::In0OrPll0(index) if self.pll0_sel[index] => self.pll_output(0),
+            Source::In0OrPll0(_) => CLOCK_FREQ_IN0,
+        }
+    }
+
+    pub(crate) fn threshold(&self, threshold: Threshold) -> u32 {
+        let (index, offset, _) = threshold.field();
+        (self.clk_th[index] >> offset) & threshold.max()
+    }
+
+    pub(crate) fn set_threshold(&mut self, threshold: Threshold, value: u32) {
+        let (index, offset, _) = threshold.field();
+        let mask = threshold.max() << offset;
+        self.clk_th[index] = (self.clk_th[index] & !mask) | ((value << offset) & mask);
+    }
+
+    /// Pick the divider (and for SPI3 and timers, the source) that gets
+    /// closest to target from below
+    pub(crate) fn fit_threshold(&mut self, threshold: Threshold, target: u32) {
+        let fit = |tree: &ClockTree| {
+            let source = tree.source_frequency(threshold.source());
+            threshold
+                .divider()
+                .threshold(source, target, threshold.max())
+        };
+        if let Source::In0OrPll0(index) = threshold.source() {
+            let mut achieved = |pll0_sel| {
+                self.pll0_sel[index] = pll0_sel;
+                let source = self.source_frequency(threshold.source());
+                threshold.divider().frequency(source, fit(self))
+            };
+            let from_in0 = achieved(false);
+            let from_pll0 = achieved(true);
+            self.pll0_sel[index] = if from_in0 <= target && from_pll0 <= target {
+                from_pll0 > from_in0
+            } else {
+                from_pll0 < from_in0
+            };
+        }
+        let value = fit(self);
+        self.set_threshold(threshold, value);
+    }
+
+    pub(crate) fn threshold_frequency(&self, threshold: Threshold) -> u32 {
+        let source = self.source_frequency(threshold.source());
+        threshold
+            .divider()
+            .frequency(source, self.threshold(threshold))
+    }
+
+    /// Derive frequencies of every clock in the tree
+    pub(crate) fn clocks(&self) -> Clocks {
+        let th = |threshold| Hertz(self.threshold_frequency(threshold));
+        Clocks {
+            in0: Hertz(CLOCK_FREQ_IN0),
+            pll0: Hertz(self.pll_output(0)),
+            pll1: Hertz(self.pll_output(1)),
+            pll2: Hertz(self.pll_output(2)),
+            aclk: Hertz(self.aclk()),
+            apb0: Hertz(self.apb(0)),
+            apb1: Hertz(self.apb(1)),
+            apb2: Hertz(self.apb(2)),
+            sram0: th(Threshold::Sram0),
+            sram1: th(Threshold::Sram1),
+            ai: th(Threshold::Ai),
+            dvp: th(Threshold::Dvp),
+            rom: th(Threshold::Rom),
+            spi0: th(Threshold::Spi0),
+            spi1: th(Threshold::Spi1),
+            spi2: th(Threshold::Spi2),
+            spi3: th(Threshold::Spi3),
+            timer0: th(Threshold::Timer0),
+            timer1: th(Threshold::Timer1),
+            timer2: th(Threshold::Timer2),
+            i2s0: th(Threshold::I2s0),
+            i2s1: th(Threshold::I2s1),
+            i2s2: th(Threshold::I2s2),
+            i2s0_mclk: th(Threshold::I2s0Mclk),
+            i2s1_mclk: th(Threshold::I2s1Mclk),
+            i2s2_mclk: th(Threshold::I2s2Mclk),
+            i2c0: th(Threshold::I2c0),
+            i2c1: th(Threshold::I2c1),
+            i2c2: th(Threshold::I2c2),
+            wdt0: th(Threshold::Wdt0),
+            wdt1: th(Threshold::Wdt1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(nr: u8, nf: u8, od: u8) -> PllParams {
+        PllParams { nr, nf, od }
+    }
+
+    #[test]
+    fn pll_search_matches_sdk() {
+        // (requested, (nr, nf, od), achieved) as computed by the Kendryte SDK
+        let cases = [
+            (800_000_000, params(1, 31, 1), 806_000_000),
+            (400_000_000, params(1, 46, 3), 398_666_666),
+            (45_158_400, params(1, 26, 15), 45_066_666),
+            (806_000_000, params(1, 31, 1), 806_000_000),
+            (390_000_000, params(1, 15, 1), 390_000_000),
+        ];
+        for (freq, expected, achieved) in cases {
+            let solution = PllParams::calculate(CLOCK_FREQ_IN0, freq).unwrap();
+            assert_eq!(solution.params, expected, "requested {}", freq);
+            assert_eq!(solution.frequency, achieved);
+            assert_eq!(solution.error, achieved as i64 - freq as i64);
+        }
+    }
+
+    #[test]
+    fn pll_search_honours_limits_and_is_optimal() {
+        let inputs = [CLOCK_FREQ_IN0, 390_000_000, 806_000_000];
+        for freq_in in inputs {
+            for freq in (10_000_000..=1_800_000_000).step_by(7_777_777) {
+                let solution = PllParams::calculate(freq_in, freq).unwrap();
+                let p = solution.params;
+                let fin = freq_in as u64;
+                assert!((1..=PLL_NR_MAX).contains(&p.nr));
+                assert!((1..=PLL_NF_MAX).contains(&p.nf));
+                assert!((1..=PLL_OD_MAX).contains(&p.od));
+                assert!((PLL_REF_MIN..=PLL_REF_MAX).contains(&(fin / p.nr as u64)));
+                let vco = fin * p.nf as u64 / p.nr as u64;
+                assert!((PLL_VCO_MIN..=PLL_VCO_MAX).contains(&vco));
+                // no valid parameter set gets closer
+                let error = |p: PllParams| {
+                    let div = p.nr as u64 * p.od as u64;
+                    ((fin * p.nf as u64).abs_diff(freq as u64 * div), div)
+                };
+                let (num, div) = error(p);
+                for nr in 1..=PLL_NR_MAX {
+                    if !(PLL_REF_MIN..=PLL_REF_MAX).contains(&(fin / nr as u64)) {
+                        continue;
+                    }
+                    for nf in 1..=PLL_NF_MAX {
+                        let vco = fin * nf as u64 / nr as u64;
+                        if !(PLL_VCO_MIN..=PLL_VCO_MAX).contains(&vco) {
+                            continue;
+                        }
+                        for od in 1..=PLL_OD_MAX {
+                            let (other_num, other_div) = error(params(nr, nf, od));
+                            assert!(num * other_div <= other_num * div);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pll_search_rejects_low_reference() {
+        assert_eq!(PllParams::calculate(10_000_000, 400_000_000), None);
+    }
+
+    #[test]
+    fn pll_frequency_is_exact() {
+        assert_eq!(params(1, 46, 3).frequency(CLOCK_FREQ_IN0), 398_666_666);
+        assert_eq!(params(3, 64, 16).frequency(806_000_000), 1_074_666_666);
+    }
+}
@@ -1,21 +1,51 @@
 //! Time units
+//!
+//! Frequencies and durations are plain `u32` newtypes. Durations convert to
+//! CLINT `mtime` ticks for given [`Clocks`], which is what [`Instant`] counts.
+//! With the `fugit` feature enabled, every type converts from and into its
+//! [`fugit`] counterpart.
+
+use crate::clint::mtime;
+use crate::clock::Clocks;
+use core::convert::TryFrom;
+use core::ops::{Add, Sub};
 
 /// Bits per second
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub struct Bps(pub u32);
 
 /// Hertz
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub struct Hertz(pub u32);
 
 /// KiloHertz
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub struct KiloHertz(pub u32);
 
 /// MegaHertz
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub struct MegaHertz(pub u32);
 
+/// Nanoseconds
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NanoSeconds(pub u32);
+
+/// Microseconds
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MicroSeconds(pub u32);
+
+/// Milliseconds
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MilliSeconds(pub u32);
+
+/// Seconds
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Seconds(pub u32);
+
+/// Point in time, as a count of CLINT `mtime` ticks since reset
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Instant(pub u64);
+
 /// Extension trait that adds convenience methods to the `u32` type
 pub trait U32Ext {
     /// Wrap in `Bps`
@@ -29,6 +59,18 @@ pub trait U32Ext {
 
     /// Wrap in `MegaHertz`
     fn mhz(self) -> MegaHertz;
+
+    /// Wrap in `NanoSeconds`
+    fn ns(self) -> NanoSeconds;
+
+    /// Wrap in `MicroSeconds`
+    fn us(self) -> MicroSeconds;
+
+    /// Wrap in `MilliSeconds`
+    fn ms(self) -> MilliSeconds;
+
+    /// Wrap in `Seconds`
+    fn s(self) -> Seconds;
 }
 
 impl U32Ext for u32 {
@@ -47,6 +89,22 @@ impl U32Ext for u32 {
     fn mhz(self) -> MegaHertz {
         MegaHertz(self)
     }
+
+    fn ns(self) -> NanoSeconds {
+        NanoSeconds(self)
+    }
+
+    fn us(self) -> MicroSeconds {
+        MicroSeconds(self)
+    }
+
+    fn ms(self) -> MilliSeconds {
+        MilliSeconds(self)
+    }
+
+    fn s(self) -> Seconds {
+        Seconds(self)
+    }
 }
 
 impl From<KiloHertz> for Hertz {
@@ -66,3 +124,163 @@ impl From<MegaHertz> for KiloHertz {
         KiloHertz(src.0 * 1_000)
     }
 }
+
+/// Error of a conversion or arithmetic operation going out of range
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Overflow;
+
+impl Hertz {
+    /// Returns the period of one cycle, rounded down; zero Hertz has no period
+    pub fn period(self) -> Option<NanoSeconds> {
+        match self.0 {
+            0 => None,
+            hz => Some(NanoSeconds(1_000_000_000 / hz)),
+        }
+    }
+}
+
+macro_rules! def_durations {
+    ($($Duration: ident: $nanos: expr;)+) => {
+$(
+impl $Duration {
+    /// Checked addition, `None` on overflow
+    pub fn checked_add(self, rhs: Self) -> Option<Self> {
+        self.0.checked_add(rhs.0).map($Duration)
+    }
+
+    /// Checked subtraction, `None` if rhs is longer
+    pub fn checked_sub(self, rhs: Self) -> Option<Self> {
+        self.0.checked_sub(rhs.0).map($Duration)
+    }
+
+    /// Checked multiplication, `None` on overflow
+    pub fn checked_mul(self, rhs: u32) -> Option<Self> {
+        self.0.checked_mul(rhs).map($Duration)
+    }
+
+    /// Returns the frequency of a cycle lasting this duration, rounded down
+    pub fn frequency(self) -> Option<Hertz> {
+        match self.0 as u64 * $nanos {
+            0 => None,
+            ns => Some(Hertz((1_000_000_000 / ns) as u32)),
+        }
+    }
+
+    /// Returns the number of CLINT `mtime` ticks in this duration, rounded up
+    pub fn to_ticks(self, clocks: &Clocks) -> u64 {
+        let ns = self.0 as u128 * $nanos;
+        (ns * clocks.mtime().0 as u128).div_ceil(1_000_000_000) as u64
+    }
+
+    /// Returns the duration of a number of CLINT `mtime` ticks, rounded down
+    pub fn from_ticks(ticks: u64, clocks: &Clocks) -> Option<Self> {
+        let ns = ticks as u128 * 1_000_000_000 / clocks.mtime().0 as u128;
+        u32::try_from(ns / $nanos).ok().map($Duration)
+    }
+}
+
+impl Add for $Duration {
+    type Output = $Duration;
+
+    /// Panics on overflow; see `checked_add`
+    fn add(self, rhs: Self) -> Self {
+        self.checked_add(rhs).expect(concat!(stringify!($Duration), " overflow"))
+    }
+}
+
+impl Sub for $Duration {
+    type Output = $Duration;
+
+    /// Panics on underflow; see `checked_sub`
+    fn sub(self, rhs: Self) -> Self {
+        self.checked_sub(rhs).expect(concat!(stringify!($Duration), " underflow"))
+    }
+}
+)+
+    };
+}
+
+def_durations! {
+    NanoSeconds: 1;
+    MicroSeconds: 1_000;
+    MilliSeconds: 1_000_000;
+    Seconds: 1_000_000_000;
+}
+
+macro_rules! def_duration_conversions {
+    ($($Coarse: ident => $Fine: ident: $factor: expr;)+) => {
+$(
+impl TryFrom<$Coarse> for $Fine {
+    type Error = Overflow;
+
+    fn try_from(src: $Coarse) -> Result<$Fine, Overflow> {
+        src.0.checked_mul($factor).map($Fine).ok_or(Overflow)
+    }
+}
+)+
+    };
+}
+
+def_duration_conversions! {
+    Seconds => MilliSeconds: 1_000;
+    Seconds => MicroSeconds: 1_000_000;
+    Seconds => NanoSeconds: 1_000_000_000;
+    MilliSeconds => MicroSeconds: 1_000;
+    MilliSeconds => NanoSeconds: 1_000_000;
+    MicroSeconds => NanoSeconds: 1_000;
+}
+
+impl Instant {
+    /// Reads the current time from CLINT `mtime`
+    pub fn now() -> Self {
+        Instant(mtime::read())
+    }
+
+    /// Checked addition of a number of ticks, `None` on overflow
+    pub fn checked_add(self, ticks: u64) -> Option<Self> {
+        self.0.checked_add(ticks).map(Instant)
+    }
+
+    /// Checked subtraction of a number of ticks, `None` on underflow
+    pub fn checked_sub(self, ticks: u64) -> Option<Self> {
+        self.0.checked_sub(ticks).map(Instant)
+    }
+
+    /// Number of ticks from earlier to self, `None` if earlier is later
+    pub fn checked_ticks_since(self, earlier: Instant) -> Option<u64> {
+        self.0.checked_sub(earlier.0)
+    }
+}
+
+#[cfg(feature = "fugit")]
+mod fugit_conversions {
+    use super::*;
+
+    macro_rules! def_fugit_conversions {
+        ($($Type: ident <=> $Fugit: ty: ($from: ident, $into: ident);)+) => {
+    $(
+    impl From<$Type> for $Fugit {
+        fn from(src: $Type) -> $Fugit {
+            <$Fugit>::$from(src.0)
+        }
+    }
+
+    impl From<$Fugit> for $Type {
+        fn from(src: $Fugit) -> $Type {
+            $Type(src.$into())
+        }
+    }
+    )+
+        };
+    }
+
+    def_fugit_conversions! {
+        Hertz <=> fugit::HertzU32: (from_raw, raw);
+        KiloHertz <=> fugit::KilohertzU32: (from_raw, raw);
+        MegaHertz <=> fugit::MegahertzU32: (from_raw, raw);
+        NanoSeconds <=> fugit::NanosDurationU32: (from_ticks, ticks);
+        MicroSeconds <=> fugit::MicrosDurationU32: (from_ticks, ticks);
+        MilliSeconds <=> fugit::MillisDurationU32: (from_ticks, ticks);
+        Seconds <=> fugit::SecsDurationU32: (from_ticks, ticks);
+    }
+}